@@ -1,68 +1,284 @@
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
+use crate::options;
+
 enum QVariant {
     Part1,
     Part2,
+    Lint,
 }
 
-fn find_illegal_closing_and_complete(
-    input: &str,
-    complement_map: &HashMap<char, char>,
-) -> (Option<char>, Option<Vec<char>>) {
-    let mut stack = vec![];
-    for c in input.chars() {
-        if complement_map.contains_key(&c) {
-            stack.push(complement_map.get(&c).unwrap().clone());
-        } else {
-            let maybe_expected = stack.pop();
-            if maybe_expected.is_none() {
-                panic!("This should not be possible!");
+/**
+ * The bracket pairs, and what each closer scores when it's illegal (part 1)
+ * or missing (part 2)
+ */
+struct BracketConfig {
+    complement_map: HashMap<char, char>,
+    illegal_score_map: HashMap<char, u64>,
+    missing_score_map: HashMap<char, u64>,
+}
+
+impl BracketConfig {
+    fn from_pairs(pairs: &[(char, char, u64, u64)]) -> BracketConfig {
+        let mut config = BracketConfig {
+            complement_map: HashMap::new(),
+            illegal_score_map: HashMap::new(),
+            missing_score_map: HashMap::new(),
+        };
+
+        for (open, close, illegal, missing) in pairs {
+            if config.complement_map.contains_key(open) || config.is_closer(*open) {
+                panic!("'{}' is used by more than one bracket pair", open);
+            }
+            if config.complement_map.contains_key(close) || config.is_closer(*close) {
+                panic!("'{}' is used by more than one bracket pair", close);
+            }
+
+            config.complement_map.insert(*open, *close);
+            config.illegal_score_map.insert(*close, *illegal);
+            config.missing_score_map.insert(*close, *missing);
+        }
+
+        config
+    }
+
+    fn default_pairs() -> BracketConfig {
+        BracketConfig::from_pairs(&[
+            ('(', ')', 3, 1),
+            ('[', ']', 57, 2),
+            ('{', '}', 1197, 3),
+            ('<', '>', 25137, 4),
+        ])
+    }
+
+    /**
+     * One pair per line: the opener, the closer, then the illegal and missing scores,
+     * e.g. `( ) 3 1`
+     */
+    fn load(path: &str) -> BracketConfig {
+        let file = File::open(path).unwrap();
+        let reader = BufReader::new(file);
+
+        let mut pairs = vec![];
+        for line in reader.lines() {
+            let text = line.unwrap();
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            let bracket = |field: &str| -> char {
+                let mut chars = field.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => panic!("Bracket '{}' should be a single character", field),
+                }
+            };
+
+            match fields[..] {
+                [open, close, illegal, missing] => pairs.push((
+                    bracket(open),
+                    bracket(close),
+                    illegal.parse::<u64>().unwrap(),
+                    missing.parse::<u64>().unwrap(),
+                )),
+                _ => panic!(
+                    "Bracket pair '{}' should be opener, closer, illegal score and missing score",
+                    text
+                ),
+            }
+        }
+
+        BracketConfig::from_pairs(&pairs)
+    }
+
+    /**
+     * -o brackets=path/to/pairs, otherwise the puzzle's four pairs
+     */
+    fn from_options() -> BracketConfig {
+        match options::get::<String>("brackets") {
+            Some(path) => BracketConfig::load(&path),
+            None => BracketConfig::default_pairs(),
+        }
+    }
+
+    fn is_closer(&self, c: char) -> bool {
+        self.illegal_score_map.contains_key(&c)
+    }
+}
+
+/**
+ * Columns start at 1
+ */
+#[derive(Debug)]
+enum LintError {
+    // A closer with nothing open
+    UnexpectedCloser {
+        column: usize,
+        found: char,
+    },
+    // A closer that doesn't match the last opener
+    MismatchedPair {
+        column: usize,
+        found: char,
+        expected: char,
+        opened_at: usize,
+    },
+    // An opener still open at the end of the line
+    UnclosedOpener {
+        column: usize,
+        opener: char,
+    },
+    UnknownCharacter {
+        column: usize,
+        found: char,
+    },
+}
+
+impl LintError {
+    // Unclosed openers only mean the line is incomplete, anything else means it's corrupted
+    fn is_corruption(&self) -> bool {
+        !matches!(self, LintError::UnclosedOpener { .. })
+    }
+}
+
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LintError::UnexpectedCloser { column, found } => {
+                write!(
+                    f,
+                    "column {} - unexpected '{}' with nothing open",
+                    column, found
+                )
+            }
+            LintError::MismatchedPair {
+                column,
+                found,
+                expected,
+                opened_at,
+            } => write!(
+                f,
+                "column {} - expected '{}' to close column {}, but found '{}'",
+                column, expected, opened_at, found
+            ),
+            LintError::UnclosedOpener { column, opener } => {
+                write!(f, "column {} - '{}' is never closed", column, opener)
             }
+            LintError::UnknownCharacter { column, found } => {
+                write!(f, "column {} - '{}' isn't a bracket", column, found)
+            }
+        }
+    }
+}
 
-            let expected = maybe_expected.unwrap();
-            if c != expected {
-                return (Some(c), None);
+/**
+ * Every error in the line, in column order, with unclosed openers last.
+ *
+ * The linter carries on after an error instead of stopping. A mismatched closer still
+ * closes the last opener, since that's usually what was meant, and an unexpected closer
+ * or unknown character is skipped.
+ */
+fn lint_line(input: &str, config: &BracketConfig) -> Vec<LintError> {
+    let mut errors = vec![];
+    // (opener, column)
+    let mut stack: Vec<(char, usize)> = vec![];
+
+    for (i, c) in input.chars().enumerate() {
+        let column = i + 1;
+        if config.complement_map.contains_key(&c) {
+            stack.push((c, column));
+        } else if config.is_closer(c) {
+            match stack.pop() {
+                None => errors.push(LintError::UnexpectedCloser { column, found: c }),
+                Some((opener, opened_at)) => {
+                    let expected = config.complement_map[&opener];
+                    if c != expected {
+                        errors.push(LintError::MismatchedPair {
+                            column,
+                            found: c,
+                            expected,
+                            opened_at,
+                        });
+                    }
+                }
             }
+        } else {
+            errors.push(LintError::UnknownCharacter { column, found: c });
         }
     }
 
-    (None, Some(stack))
+    errors.extend(
+        stack
+            .into_iter()
+            .map(|(opener, column)| LintError::UnclosedOpener { column, opener }),
+    );
+
+    errors
+}
+
+// Score of the first illegal closer, if the line is corrupted
+fn illegal_score(errors: &[LintError], config: &BracketConfig) -> Option<u64> {
+    errors.iter().find_map(|error| match error {
+        LintError::UnexpectedCloser { found, .. } | LintError::MismatchedPair { found, .. } => {
+            Some(config.illegal_score_map[found])
+        }
+        _ => None,
+    })
+}
+
+/**
+ * Score of the closers that complete the line, if it's only incomplete.
+ * The innermost opener is closed first, so the unclosed openers are walked backwards.
+ */
+fn completion_score(errors: &[LintError], config: &BracketConfig) -> Option<u64> {
+    if errors.iter().any(LintError::is_corruption) {
+        return None;
+    }
+
+    let score = errors.iter().rev().fold(0, |score, error| match error {
+        LintError::UnclosedOpener { opener, .. } => {
+            (score * 5) + config.missing_score_map[&config.complement_map[opener]]
+        }
+        _ => score,
+    });
+
+    Some(score)
 }
 
 fn run_problem(variant: QVariant) {
     let file = File::open("inputs/day10/input.txt").unwrap();
     let reader = BufReader::new(file);
 
-    let complement_map: HashMap<char, char> = [('{', '}'), ('[', ']'), ('(', ')'), ('<', '>')]
-        .into_iter()
-        .collect();
-    let illegal_score_map: HashMap<char, u64> = [('}', 1197), (']', 57), (')', 3), ('>', 25137)]
-        .into_iter()
-        .collect();
-    let missing_score_map: HashMap<char, u64> = [('}', 3), (']', 2), (')', 1), ('>', 4)]
-        .into_iter()
-        .collect();
+    let config = BracketConfig::from_options();
 
     let mut illegal_closing_score = 0;
     let mut missing_closing_scores_vec = vec![];
-    for line in reader.lines() {
-        let mut missing_closing_score = 0;
+    let (mut corrupted, mut incomplete) = (0, 0);
+    for (line_num, line) in reader.lines().enumerate() {
         let input = line.unwrap();
-        let (maybe_illegal, maybe_missing_close_chars) =
-            find_illegal_closing_and_complete(&input, &complement_map);
+        let errors = lint_line(&input, &config);
 
-        if maybe_illegal.is_some() {
-            illegal_closing_score += illegal_score_map.get(&maybe_illegal.unwrap()).unwrap();
-        } else {
-            let mut missing_close_chars = maybe_missing_close_chars.unwrap();
-            while let Some(c) = missing_close_chars.pop() {
-                missing_closing_score =
-                    (missing_closing_score * 5) + missing_score_map.get(&c).unwrap();
+        if matches!(variant, QVariant::Lint) {
+            for error in &errors {
+                println!("Line {}, {}", line_num + 1, error);
             }
+        }
 
-            missing_closing_scores_vec.push(missing_closing_score);
+        if let Some(score) = illegal_score(&errors, &config) {
+            illegal_closing_score += score;
+            corrupted += 1;
+        } else if let Some(score) = completion_score(&errors, &config) {
+            // Lines with nothing wrong complete with an empty string and don't count
+            if !errors.is_empty() {
+                missing_closing_scores_vec.push(score);
+                incomplete += 1;
+            }
+        } else {
+            corrupted += 1;
         }
     }
 
@@ -71,12 +287,22 @@ fn run_problem(variant: QVariant) {
             println!("Answer - {}", illegal_closing_score);
         }
         QVariant::Part2 => {
+            if missing_closing_scores_vec.is_empty() {
+                panic!("No incomplete lines to score");
+            }
+
             missing_closing_scores_vec.sort();
             println!(
                 "Answer - {}",
                 missing_closing_scores_vec[missing_closing_scores_vec.len() / 2]
             );
         }
+        QVariant::Lint => {
+            println!(
+                "{} corrupted lines, {} incomplete lines",
+                corrupted, incomplete
+            );
+        }
     }
 }
 
@@ -87,3 +313,7 @@ pub fn part1() {
 pub fn part2() {
     run_problem(QVariant::Part2);
 }
+
+pub fn part3() {
+    run_problem(QVariant::Lint);
+}
@@ -1,5 +1,16 @@
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Every report line is stored as a u128, so this is the widest line we can read
+const MAX_REPORT_WIDTH: usize = u128::BITS as usize;
+
+const BENCHMARK_SEED: u64 = 2021;
+const BENCHMARK_LINES: usize = 1_000_000;
+const BENCHMARK_WIDTH: usize = 32;
 
 enum BinCmp {
     One,
@@ -7,139 +18,506 @@ enum BinCmp {
     Equal,
 }
 
-fn is_most_common(nums: &Vec<u32>, bit_pos: usize) -> BinCmp {
-    let mut ones = 0;
-    let mut zeros = 0;
-    let bit = 1 << bit_pos;
+#[derive(Debug, Copy, Clone)]
+enum Commonality {
+    Most,
+    Least,
+}
 
-    for num in nums {
-        if num & bit == bit {
-            ones += 1;
-        } else {
-            zeros += 1;
+/**
+ * A bit criteria rule for narrowing a report down to a single rating.
+ * For every bit position, only numbers with the most (or least) common bit survive.
+ * When both bits are equally common, numbers with `tie_break` survive instead.
+ */
+#[derive(Debug, Copy, Clone)]
+struct BitCriteria {
+    keep: Commonality,
+    tie_break: u8,
+}
+
+const OXYGEN_CRITERIA: BitCriteria = BitCriteria {
+    keep: Commonality::Most,
+    tie_break: 1,
+};
+
+const CO2_CRITERIA: BitCriteria = BitCriteria {
+    keep: Commonality::Least,
+    tie_break: 0,
+};
+
+impl BitCriteria {
+    fn bit_to_keep(&self, cmp: BinCmp) -> u8 {
+        match (cmp, self.keep) {
+            (BinCmp::Equal, _) => self.tie_break,
+            (BinCmp::One, Commonality::Most) | (BinCmp::Zero, Commonality::Least) => 1,
+            (BinCmp::Zero, Commonality::Most) | (BinCmp::One, Commonality::Least) => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FilterStep {
+    bit_pos: usize,
+    kept_bit: u8,
+    remaining: usize,
+    eliminated: usize,
+}
+
+#[derive(Debug)]
+struct FilterResult {
+    value: u128,
+    trace: Vec<FilterStep>,
+}
+
+#[derive(Debug)]
+struct DiagnosticReport {
+    width: usize,
+    numbers: Vec<u128>,
+}
+
+/**
+ * Number of set bits in every column of a report, indexed by bit position
+ * (0 is the least significant bit). All columns are filled in a single pass
+ * over the numbers, and kept up to date as numbers are removed.
+ */
+#[derive(Debug, Clone)]
+struct BitColumnCounts {
+    ones: Vec<usize>,
+    total: usize,
+}
+
+impl BitColumnCounts {
+    fn from_numbers(numbers: &[u128], width: usize) -> BitColumnCounts {
+        let mut ones = vec![0; width];
+        for num in numbers {
+            let mut remaining = *num;
+            while remaining != 0 {
+                let bit_pos = remaining.trailing_zeros() as usize;
+                ones[bit_pos] += 1;
+                remaining &= remaining - 1;
+            }
+        }
+
+        BitColumnCounts {
+            ones,
+            total: numbers.len(),
         }
     }
 
+    /**
+     * Takes a number out of the columns below `below_bit`. Filters only look at lower
+     * columns after deciding a bit, so the higher ones can be left stale.
+     */
+    fn remove_below(&mut self, num: u128, below_bit: usize) {
+        let mut remaining = num & ((1 << below_bit) - 1);
+        while remaining != 0 {
+            let bit_pos = remaining.trailing_zeros() as usize;
+            self.ones[bit_pos] -= 1;
+            remaining &= remaining - 1;
+        }
+
+        self.total -= 1;
+    }
+
+    fn most_common(&self, bit_pos: usize) -> BinCmp {
+        compare_counts(self.ones[bit_pos], self.total)
+    }
+}
+
+fn compare_counts(ones: usize, total: usize) -> BinCmp {
+    let zeros = total - ones;
+
     if ones > zeros {
-        return BinCmp::One;
+        BinCmp::One
     } else if zeros > ones {
-        return BinCmp::Zero;
+        BinCmp::Zero
     } else {
-        return BinCmp::Equal;
+        BinCmp::Equal
     }
 }
 
-fn convert_bin_to_decimal(binary_str: &str) -> u32 {
-    let len = binary_str.len();
-    let mut value: u32 = 0;
+fn convert_bin_to_decimal(binary_str: &str) -> u128 {
+    let mut value: u128 = 0;
 
-    for i in 0..len {
-        let position = len - i - 1;
-        if binary_str.chars().nth(i).unwrap() == '1' {
-            value += 1 << position;
+    for c in binary_str.chars() {
+        value <<= 1;
+        match c {
+            '1' => value |= 1,
+            '0' => {}
+            _ => panic!("Unexpected character '{}' in report line", c),
         }
     }
 
-    return value;
+    value
 }
 
-fn retain_by_bit(bin_vec: &mut Vec<u32>, bit_pos: usize, bit_value: u8) {
+/**
+ * Drops the numbers without the given bit, taking them out of the column counts too
+ */
+fn retain_by_bit(
+    bin_vec: &mut Vec<u128>,
+    counts: &mut BitColumnCounts,
+    bit_pos: usize,
+    bit_value: u8,
+) {
     let bit = 1 << bit_pos;
     let comparator = if bit_value == 1 { bit } else { 0 };
-    bin_vec.retain(|o| o & bit == comparator);
+    bin_vec.retain(|o| {
+        let keep = o & bit == comparator;
+        if !keep {
+            counts.remove_below(*o, bit_pos);
+        }
+        keep
+    });
 }
 
-pub fn part1() {
-    let file = File::open("inputs/day3/input.txt").unwrap();
-    let reader = BufReader::new(file);
+/**
+ * Applies the criteria from the most significant bit down until a single number is left.
+ * Takes the column counts of the whole report, and updates a copy as numbers are eliminated
+ * instead of recounting the survivors for every bit.
+ * Returns None for an empty report.
+ */
+fn filter_by_criteria(
+    numbers: &[u128],
+    counts: &BitColumnCounts,
+    width: usize,
+    criteria: &BitCriteria,
+) -> Option<FilterResult> {
+    let mut remaining = numbers.to_vec();
+    let mut counts = counts.clone();
+    let mut trace = vec![];
 
-    let mut numbers = vec![];
+    for i in 0..width {
+        if remaining.len() <= 1 {
+            break;
+        }
 
-    let mut maxlen: Option<usize> = None;
+        let bit_pos = width - i - 1;
+        let ones = counts.ones[bit_pos];
+        if ones == 0 || ones == remaining.len() {
+            // Every number shares this bit, so there is nothing to eliminate
+            trace.push(FilterStep {
+                bit_pos,
+                kept_bit: (ones > 0) as u8,
+                remaining: remaining.len(),
+                eliminated: 0,
+            });
+            continue;
+        }
 
-    for line in reader.lines() {
-        let text = line.unwrap();
-        numbers.push(convert_bin_to_decimal(&text));
+        let kept_bit = criteria.bit_to_keep(counts.most_common(bit_pos));
+        let before = remaining.len();
+        retain_by_bit(&mut remaining, &mut counts, bit_pos, kept_bit);
+
+        trace.push(FilterStep {
+            bit_pos,
+            kept_bit,
+            remaining: remaining.len(),
+            eliminated: before - remaining.len(),
+        });
+    }
+
+    remaining.first().map(|value| FilterResult {
+        value: *value,
+        trace,
+    })
+}
+
+/**
+ * The plain rating search the counts based filter replaced: recount the survivors'
+ * bit for every position, then retain. Only kept as the benchmark baseline.
+ */
+fn recount_rating(numbers: &[u128], width: usize, criteria: &BitCriteria) -> Option<u128> {
+    let mut remaining = numbers.to_vec();
 
-        if maxlen.is_none() {
-            maxlen = Some(text.len());
+    for i in 0..width {
+        if remaining.len() <= 1 {
+            break;
         }
+
+        let bit = 1 << (width - i - 1);
+        let ones = remaining.iter().filter(|num| *num & bit == bit).count();
+        let kept_bit = criteria.bit_to_keep(compare_counts(ones, remaining.len()));
+        let comparator = if kept_bit == 1 { bit } else { 0 };
+        remaining.retain(|num| num & bit == comparator);
     }
 
-    if numbers.len() == 0 {
-        return;
+    remaining.first().copied()
+}
+
+// Child index for a branch that doesn't exist. The root is never anyone's child.
+const NO_CHILD: u32 = 0;
+
+#[derive(Debug, Default, Copy, Clone)]
+struct TrieNode {
+    children: [u32; 2],
+    // Number of report lines passing through this node
+    count: u32,
+}
+
+/**
+ * Binary trie over the report, most significant bit first.
+ * Each node keeps the size of its subtree, so a rating is found by walking from
+ * the root to a leaf and picking a child per bit, instead of re-filtering the report.
+ */
+#[derive(Debug)]
+struct BitTrie {
+    nodes: Vec<TrieNode>,
+    width: usize,
+}
+
+impl BitTrie {
+    /**
+     * Most nodes the trie can need: depth d has at most 2^d nodes, and never more
+     * than one per line
+     */
+    fn max_nodes(lines: usize, width: usize) -> usize {
+        (0..=width)
+            .map(|depth| {
+                if depth < usize::BITS as usize - 1 {
+                    (1usize << depth).min(lines)
+                } else {
+                    lines
+                }
+            })
+            .sum()
     }
 
-    let maxlen = maxlen.unwrap();
-    let mut gamma = 0;
-    let mut epsilon = 0;
+    fn from_numbers(numbers: &[u128], width: usize) -> BitTrie {
+        let capacity = BitTrie::max_nodes(numbers.len(), width);
+        if capacity > u32::MAX as usize {
+            panic!(
+                "A report this size could need more than {} trie nodes",
+                u32::MAX
+            );
+        }
+
+        let mut nodes = Vec::with_capacity(capacity);
+        nodes.push(TrieNode::default());
 
-    for i in 0..maxlen {
-        let bit_position = maxlen - i - 1;
-        if matches!(is_most_common(&numbers, bit_position), BinCmp::One) {
-            gamma += 1 << bit_position;
-        } else {
-            epsilon += 1 << bit_position;
+        for num in numbers {
+            let mut node = 0;
+            nodes[node].count += 1;
+
+            for i in 0..width {
+                let bit = ((num >> (width - i - 1)) & 1) as usize;
+                let mut child = nodes[node].children[bit];
+                if child == NO_CHILD {
+                    nodes.push(TrieNode::default());
+                    child = (nodes.len() - 1) as u32;
+                    nodes[node].children[bit] = child;
+                }
+
+                node = child as usize;
+                nodes[node].count += 1;
+            }
         }
+
+        BitTrie { nodes, width }
     }
 
-    println!("gamma {}, epsilon {}", gamma, epsilon);
-    println!("Answer - {}", gamma * epsilon);
+    fn child_count(&self, node: usize, bit: usize) -> usize {
+        match self.nodes[node].children[bit] {
+            NO_CHILD => 0,
+            child => self.nodes[child as usize].count as usize,
+        }
+    }
+
+    fn find_rating(&self, criteria: &BitCriteria) -> Option<u128> {
+        if self.nodes[0].count == 0 {
+            return None;
+        }
+
+        let mut node = 0;
+        let mut value: u128 = 0;
+
+        for _ in 0..self.width {
+            let zeros = self.child_count(node, 0);
+            let ones = self.child_count(node, 1);
+
+            // A missing side means every remaining line shares this bit
+            let bit = if zeros == 0 {
+                1
+            } else if ones == 0 {
+                0
+            } else {
+                criteria.bit_to_keep(compare_counts(ones, ones + zeros)) as usize
+            };
+
+            value = (value << 1) | bit as u128;
+            node = self.nodes[node].children[bit] as usize;
+        }
+
+        Some(value)
+    }
 }
 
-pub fn part2() {
-    let file = File::open("inputs/day3/input.txt").unwrap();
-    let reader = BufReader::new(file);
+fn generate_report(seed: u64, lines: usize, width: usize) -> Vec<u128> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mask = if width == MAX_REPORT_WIDTH {
+        u128::MAX
+    } else {
+        (1 << width) - 1
+    };
 
+    (0..lines).map(|_| rng.gen::<u128>() & mask).collect()
+}
+
+fn print_trace(name: &str, result: &FilterResult) {
+    println!("{} trace:", name);
+    for step in &result.trace {
+        println!(
+            "  bit {:>3} kept {} - eliminated {}, {} remaining",
+            step.bit_pos, step.kept_bit, step.eliminated, step.remaining
+        );
+    }
+}
+
+fn parse_input(reader: BufReader<File>) -> DiagnosticReport {
     let mut numbers = vec![];
-    let mut maxlen: Option<usize> = None;
+    let mut width: Option<usize> = None;
 
     for line in reader.lines() {
         let text = line.unwrap();
-        numbers.push(convert_bin_to_decimal(&text));
+        if text.is_empty() {
+            continue;
+        }
 
-        if maxlen.is_none() {
-            maxlen = Some(text.len());
+        match width {
+            None => {
+                if text.len() > MAX_REPORT_WIDTH {
+                    panic!(
+                        "Report lines are {} bits wide, at most {} are supported",
+                        text.len(),
+                        MAX_REPORT_WIDTH
+                    );
+                }
+                width = Some(text.len());
+            }
+            Some(w) if w != text.len() => {
+                panic!("Report line '{}' is not {} bits wide", text, w);
+            }
+            _ => {}
         }
+
+        numbers.push(convert_bin_to_decimal(&text));
+    }
+
+    DiagnosticReport {
+        width: width.unwrap_or(0),
+        numbers,
     }
+}
 
-    if numbers.len() == 0 {
+fn print_product(a: u128, b: u128) {
+    match a.checked_mul(b) {
+        Some(product) => println!("Answer - {}", product),
+        None => println!("Answer - {} * {} (overflows u128)", a, b),
+    }
+}
+
+pub fn part1() {
+    let file = File::open("inputs/day3/input.txt").unwrap();
+    let reader = BufReader::new(file);
+
+    let report = parse_input(reader);
+    if report.numbers.is_empty() {
         return;
     }
 
-    let mut oxygen_vec = numbers.clone();
-    let mut co_vec = numbers.clone();
-    let maxlen = maxlen.unwrap();
+    let counts = BitColumnCounts::from_numbers(&report.numbers, report.width);
+    let mut gamma: u128 = 0;
+    let mut epsilon: u128 = 0;
 
-    for i in 0..maxlen {
-        let bit_position = maxlen - i - 1;
-        if oxygen_vec.len() > 1 {
-            match is_most_common(&oxygen_vec, bit_position) {
-                BinCmp::One | BinCmp::Equal => {
-                    retain_by_bit(&mut oxygen_vec, bit_position, 1);
-                }
-                BinCmp::Zero => {
-                    retain_by_bit(&mut oxygen_vec, bit_position, 0);
-                }
-            }
+    for bit_position in 0..report.width {
+        if matches!(counts.most_common(bit_position), BinCmp::One) {
+            gamma |= 1 << bit_position;
+        } else {
+            epsilon |= 1 << bit_position;
         }
+    }
 
-        if co_vec.len() > 1 {
-            match is_most_common(&co_vec, bit_position) {
-                BinCmp::One | BinCmp::Equal => {
-                    retain_by_bit(&mut co_vec, bit_position, 0);
-                }
-                BinCmp::Zero => {
-                    retain_by_bit(&mut co_vec, bit_position, 1);
-                }
-            }
-        }
+    println!("gamma {}, epsilon {}", gamma, epsilon);
+    print_product(gamma, epsilon);
+}
 
-        if oxygen_vec.len() == 1 && co_vec.len() == 1 {
-            break;
-        }
+pub fn part2() {
+    let file = File::open("inputs/day3/input.txt").unwrap();
+    let reader = BufReader::new(file);
+
+    let report = parse_input(reader);
+    if report.numbers.is_empty() {
+        return;
     }
 
-    println!("oxygen ({}), co2 ({})", oxygen_vec[0], co_vec[0],);
-    println!("Answer - {}", oxygen_vec[0] * &co_vec[0]);
+    let counts = BitColumnCounts::from_numbers(&report.numbers, report.width);
+    let oxygen =
+        filter_by_criteria(&report.numbers, &counts, report.width, &OXYGEN_CRITERIA).unwrap();
+    let co2 = filter_by_criteria(&report.numbers, &counts, report.width, &CO2_CRITERIA).unwrap();
+
+    print_trace("oxygen", &oxygen);
+    print_trace("co2", &co2);
+
+    println!("oxygen ({}), co2 ({})", oxygen.value, co2.value);
+    print_product(oxygen.value, co2.value);
+}
+
+/**
+ * Compares the rating searches on a generated report: recounting and retaining per bit,
+ * retaining with column counts kept up to date, and walking a trie
+ */
+pub fn part3() {
+    let numbers = generate_report(BENCHMARK_SEED, BENCHMARK_LINES, BENCHMARK_WIDTH);
+    println!(
+        "Generated {} lines of {} bits (seed {})",
+        numbers.len(),
+        BENCHMARK_WIDTH,
+        BENCHMARK_SEED
+    );
+
+    let now = Instant::now();
+    let recount_oxygen = recount_rating(&numbers, BENCHMARK_WIDTH, &OXYGEN_CRITERIA).unwrap();
+    let recount_co2 = recount_rating(&numbers, BENCHMARK_WIDTH, &CO2_CRITERIA).unwrap();
+    println!(
+        "recount - oxygen ({}), co2 ({}) in {:.2?}",
+        recount_oxygen,
+        recount_co2,
+        now.elapsed()
+    );
+
+    let now = Instant::now();
+    let counts = BitColumnCounts::from_numbers(&numbers, BENCHMARK_WIDTH);
+    let oxygen = filter_by_criteria(&numbers, &counts, BENCHMARK_WIDTH, &OXYGEN_CRITERIA).unwrap();
+    let co2 = filter_by_criteria(&numbers, &counts, BENCHMARK_WIDTH, &CO2_CRITERIA).unwrap();
+    println!(
+        "counts  - oxygen ({}), co2 ({}) in {:.2?}",
+        oxygen.value,
+        co2.value,
+        now.elapsed()
+    );
+
+    let now = Instant::now();
+    let trie = BitTrie::from_numbers(&numbers, BENCHMARK_WIDTH);
+    let build_elapsed = now.elapsed();
+    let trie_oxygen = trie.find_rating(&OXYGEN_CRITERIA).unwrap();
+    let trie_co2 = trie.find_rating(&CO2_CRITERIA).unwrap();
+    let total_elapsed = now.elapsed();
+    println!(
+        "trie    - oxygen ({}), co2 ({}) in {:.2?} ({:.2?} building {} nodes, {:.2?} walking)",
+        trie_oxygen,
+        trie_co2,
+        total_elapsed,
+        build_elapsed,
+        trie.nodes.len(),
+        total_elapsed - build_elapsed
+    );
+
+    println!("The trie only wins on the walk, building it costs more than either filter");
+
+    assert_eq!(recount_oxygen, oxygen.value);
+    assert_eq!(recount_co2, co2.value);
+    assert_eq!(oxygen.value, trie_oxygen);
+    assert_eq!(co2.value, trie_co2);
+
+    print_product(trie_oxygen, trie_co2);
 }
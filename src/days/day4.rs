@@ -1,80 +1,159 @@
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
-const BINGO_BOARD_SIZE: usize = 5;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::options;
+
+// Defaults for the generator, matching the shape of the puzzle input
+const GENERATOR_BOARDS: usize = 100;
+const GENERATOR_BOARD_SIZE: usize = 5;
+const GENERATOR_NUMBERS: u32 = 100;
+const GENERATOR_MAX_ATTEMPTS: u64 = 1000;
+
+/**
+ * Which lines count as a bingo. Rows and columns always win,
+ * diagonals only when enabled and the board is square.
+ */
+#[derive(Debug, Copy, Clone)]
+struct BingoRules {
+    diagonals: bool,
+}
+
+impl BingoRules {
+    fn from_options() -> BingoRules {
+        BingoRules {
+            diagonals: options::flag("diagonals"),
+        }
+    }
+}
 
 #[derive(Debug)]
 struct BingoBoard {
-    board_state: [[bool; BINGO_BOARD_SIZE]; BINGO_BOARD_SIZE],
+    rows: usize,
+    cols: usize,
+    board_state: Vec<Vec<bool>>,
 
     // Easy lookup to go from bingo entry to point on the board
     num_state_map: HashMap<u32, (usize, usize)>,
 }
 
-impl Default for BingoBoard {
-    fn default() -> BingoBoard {
+impl BingoBoard {
+    fn new(rows: usize, cols: usize) -> BingoBoard {
         BingoBoard {
-            board_state: [[false; BINGO_BOARD_SIZE]; BINGO_BOARD_SIZE],
+            rows,
+            cols,
+            board_state: vec![vec![false; cols]; rows],
             num_state_map: HashMap::new(),
         }
     }
-}
 
-impl BingoBoard {
     fn try_mark_entry(&mut self, entry: u32) -> bool {
-        if self.num_state_map.contains_key(&entry) {
-            let point = self.num_state_map.get(&entry).unwrap();
-            self.board_state[point.0][point.1] = true;
-            true
-        } else {
-            false
+        match self.num_state_map.get(&entry) {
+            Some(point) => {
+                self.board_state[point.0][point.1] = true;
+                true
+            }
+            None => false,
         }
     }
 
     fn add_entry(&mut self, row: usize, col: usize, entry: u32) {
-        self.num_state_map.insert(entry, (row, col));
+        if self.num_state_map.insert(entry, (row, col)).is_some() {
+            panic!("Number {} appears more than once on a board", entry);
+        }
+    }
+
+    fn is_square(&self) -> bool {
+        self.rows == self.cols
     }
 
     /**
-     * To save a little time, we will only look at the row and column
+     * To save a little time, we will only look at the lines
      * to which that entry belongs
      */
-    fn is_entry_winner(&self, entry: u32) -> bool {
-        if !self.num_state_map.contains_key(&entry) {
-            return false;
+    fn is_entry_winner(&self, entry: u32, rules: &BingoRules) -> bool {
+        let point = match self.num_state_map.get(&entry) {
+            Some(point) => point,
+            None => return false,
+        };
+
+        // iterate column. col is fixed
+        if (0..self.rows).all(|row| self.board_state[row][point.1]) {
+            return true;
         }
 
-        let point = self.num_state_map.get(&entry).unwrap();
+        // iterate row. row is fixed
+        if (0..self.cols).all(|col| self.board_state[point.0][col]) {
+            return true;
+        }
 
-        let mut row_all_true = true;
-        // iterate row. col is fixed
-        for row in 0..BINGO_BOARD_SIZE {
-            if !self.board_state[row][point.1] {
-                row_all_true = false;
-                break;
+        if rules.diagonals && self.is_square() {
+            let size = self.rows;
+            if point.0 == point.1 && (0..size).all(|i| self.board_state[i][i]) {
+                return true;
+            }
+
+            if point.0 + point.1 == size - 1 && (0..size).all(|i| self.board_state[i][size - i - 1])
+            {
+                return true;
             }
         }
 
-        if row_all_true {
-            return true;
+        false
+    }
+
+    /**
+     * The turn this board would win on, without simulating the draws.
+     * A line completes on the latest draw of its cells, and the board wins on the
+     * earliest completed line. Cells that are never drawn block their lines.
+     */
+    fn win_turn(&self, draw_index: &HashMap<u32, usize>, rules: &BingoRules) -> Option<usize> {
+        let mut turn_grid = vec![vec![None; self.cols]; self.rows];
+        for (entry, point) in &self.num_state_map {
+            turn_grid[point.0][point.1] = draw_index.get(entry).copied();
         }
 
-        let mut col_all_true = true;
-        // iterate col. row is fixed
-        for col in 0..BINGO_BOARD_SIZE {
-            if !self.board_state[point.0][col] {
-                col_all_true = false;
-                break;
-            }
+        self.lines(rules)
+            .iter()
+            .filter_map(|line| {
+                line.iter()
+                    .map(|(row, col)| turn_grid[*row][*col])
+                    .try_fold(0, |latest, turn| turn.map(|t| t.max(latest)))
+            })
+            .min()
+    }
+
+    fn lines(&self, rules: &BingoRules) -> Vec<Vec<(usize, usize)>> {
+        let mut lines = vec![];
+        for row in 0..self.rows {
+            lines.push((0..self.cols).map(|col| (row, col)).collect());
         }
 
-        if col_all_true {
-            return true;
+        for col in 0..self.cols {
+            lines.push((0..self.rows).map(|row| (row, col)).collect());
         }
 
-        false
+        if rules.diagonals && self.is_square() {
+            let size = self.rows;
+            lines.push((0..size).map(|i| (i, i)).collect());
+            lines.push((0..size).map(|i| (i, size - i - 1)).collect());
+        }
+
+        lines
+    }
+
+    /**
+     * Sum of the entries not drawn by the end of the given turn
+     */
+    fn count_unmarked_after(&self, draw_index: &HashMap<u32, usize>, turn: usize) -> u32 {
+        self.num_state_map
+            .keys()
+            .filter(|entry| draw_index.get(entry).is_none_or(|t| *t > turn))
+            .sum()
     }
 
     fn count_unmarked(&self) -> u32 {
@@ -89,8 +168,31 @@ impl BingoBoard {
     }
 }
 
-fn parse_input(reader: BufReader<File>) -> (Vec<u32>, Vec<BingoBoard>) {
-    let mut line_iter = reader.lines().peekable();
+fn build_board(rows: &[Vec<u32>], board_index: usize) -> BingoBoard {
+    let cols = rows[0].len();
+    for row in rows {
+        if row.len() != cols {
+            panic!(
+                "Board {} has rows of different lengths ({} and {})",
+                board_index,
+                cols,
+                row.len()
+            );
+        }
+    }
+
+    let mut board = BingoBoard::new(rows.len(), cols);
+    for (row, nums) in rows.iter().enumerate() {
+        for (col, num) in nums.iter().enumerate() {
+            board.add_entry(row, col, *num);
+        }
+    }
+
+    board
+}
+
+fn parse_input<R: BufRead>(reader: R) -> (Vec<u32>, Vec<BingoBoard>) {
+    let mut line_iter = reader.lines();
 
     let line = line_iter.next().unwrap().unwrap();
     let bingo_nums = line
@@ -98,45 +200,231 @@ fn parse_input(reader: BufReader<File>) -> (Vec<u32>, Vec<BingoBoard>) {
         .map(|token| token.parse::<u32>().unwrap())
         .collect();
 
-    // Skip the newline
-    line_iter.next();
-
-    // Iterate over board input. Boards are separated by a newline
+    // Iterate over board input. Boards are separated by one or more empty lines
     let mut boards: Vec<BingoBoard> = vec![];
-    while line_iter.peek().is_some() {
-        let mut board = BingoBoard {
-            ..Default::default()
-        };
-        let mut row = 0;
-        let mut col = 0;
-        while let Some(line_opt) = line_iter.next() {
-            let line = line_opt.unwrap();
-            if line.len() == 0 {
-                break;
+    let mut rows: Vec<Vec<u32>> = vec![];
+    for line_opt in line_iter {
+        let line = line_opt.unwrap();
+        if line.trim().is_empty() {
+            if !rows.is_empty() {
+                boards.push(build_board(&rows, boards.len()));
+                rows.clear();
             }
+            continue;
+        }
 
-            let board_nums: Vec<u32> = line
-                .split_whitespace()
+        rows.push(
+            line.split_whitespace()
                 .map(|token| token.parse::<u32>().unwrap())
-                .collect();
-            for num in board_nums {
-                board.add_entry(row, col, num);
-                col += 1;
+                .collect(),
+        );
+    }
+
+    if !rows.is_empty() {
+        boards.push(build_board(&rows, boards.len()));
+    }
+
+    (bingo_nums, boards)
+}
+
+#[derive(Debug)]
+struct BoardWin {
+    board: usize,
+    number: u32,
+    // 0 based index into the drawn numbers
+    turn: usize,
+    unmarked_count: u32,
+}
+
+impl BoardWin {
+    fn score(&self) -> u32 {
+        self.number * self.unmarked_count
+    }
+}
+
+#[derive(Debug)]
+struct GameResult {
+    // In the order the boards won. Boards winning on the same number keep their input order
+    winners: Vec<BoardWin>,
+    never_won: Vec<usize>,
+}
+
+fn play_bingo(bingo_nums: &[u32], boards: &mut [BingoBoard], rules: &BingoRules) -> GameResult {
+    let mut winners = vec![];
+    let mut has_won = vec![false; boards.len()];
+
+    for (turn, num) in bingo_nums.iter().enumerate() {
+        for (i, board) in boards.iter_mut().enumerate() {
+            if has_won[i] {
+                continue;
+            }
+
+            board.try_mark_entry(*num);
+            if board.is_entry_winner(*num, rules) {
+                has_won[i] = true;
+                winners.push(BoardWin {
+                    board: i,
+                    number: *num,
+                    turn,
+                    unmarked_count: board.count_unmarked(),
+                });
             }
+        }
 
-            row += 1;
-            col = 0;
+        if winners.len() == boards.len() {
+            break;
         }
+    }
+
+    let never_won = (0..boards.len()).filter(|i| !has_won[*i]).collect();
+
+    GameResult { winners, never_won }
+}
 
-        boards.push(board);
+/**
+ * First draw index of every number. Repeated draws are ignored since the cell is already marked.
+ */
+fn build_draw_index(bingo_nums: &[u32]) -> HashMap<u32, usize> {
+    let mut draw_index = HashMap::new();
+    for (turn, num) in bingo_nums.iter().enumerate() {
+        draw_index.entry(*num).or_insert(turn);
     }
 
-    (bingo_nums, boards)
+    draw_index
+}
+
+/**
+ * Same result as play_bingo, but computed per board in O(cells) from the draw index
+ */
+fn analyze_bingo(bingo_nums: &[u32], boards: &[BingoBoard], rules: &BingoRules) -> GameResult {
+    let draw_index = build_draw_index(bingo_nums);
+
+    let mut winners = vec![];
+    let mut never_won = vec![];
+    for (i, board) in boards.iter().enumerate() {
+        match board.win_turn(&draw_index, rules) {
+            Some(turn) => winners.push(BoardWin {
+                board: i,
+                number: bingo_nums[turn],
+                turn,
+                unmarked_count: board.count_unmarked_after(&draw_index, turn),
+            }),
+            None => never_won.push(i),
+        }
+    }
+
+    // Stable, so boards winning on the same turn stay in input order like the simulation
+    winners.sort_by_key(|win| win.turn);
+
+    GameResult { winners, never_won }
+}
+
+fn print_timeline(game: &GameResult) {
+    for (place, win) in game.winners.iter().enumerate() {
+        println!(
+            "#{} - board {} on turn {} (number {}), unmarked count {}, score {}",
+            place + 1,
+            win.board,
+            win.turn + 1,
+            win.number,
+            win.unmarked_count,
+            win.score()
+        );
+    }
+
+    if !game.never_won.is_empty() {
+        println!("Boards that never won - {:?}", game.never_won);
+    }
+}
+
+/**
+ * Draws on which more than one board won, as (turn, boards)
+ */
+fn find_ties(game: &GameResult) -> Vec<(usize, Vec<usize>)> {
+    let mut ties: Vec<(usize, Vec<usize>)> = vec![];
+    for win in &game.winners {
+        match ties.last_mut() {
+            Some((turn, boards)) if *turn == win.turn => boards.push(win.board),
+            _ => ties.push((win.turn, vec![win.board])),
+        }
+    }
+
+    ties.retain(|(_, boards)| boards.len() > 1);
+    ties
+}
+
+/**
+ * A game only has a unique answer if the first and last winners don't share their draw
+ */
+fn has_unique_answers(game: &GameResult) -> bool {
+    let (first, last) = match (game.winners.first(), game.winners.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return false,
+    };
+
+    let first_ties = game.winners.iter().filter(|w| w.turn == first.turn).count();
+    let last_ties = game.winners.iter().filter(|w| w.turn == last.turn).count();
+
+    first_ties == 1 && last_ties == 1 && game.never_won.is_empty()
+}
+
+fn print_ties(game: &GameResult) {
+    for (turn, boards) in find_ties(game) {
+        println!("Tie on turn {} - boards {:?}", turn + 1, boards);
+    }
+}
+
+/**
+ * Writes a game in the same format parse_input reads: the draws on one line,
+ * then each board as whitespace aligned rows, separated by empty lines.
+ */
+fn generate_game(seed: u64, num_boards: usize, size: usize, max_number: u32) -> String {
+    if (max_number as usize) < size * size {
+        panic!(
+            "Need at least {} numbers to fill a {}x{} board",
+            size * size,
+            size,
+            size
+        );
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut pool: Vec<u32> = (0..max_number).collect();
+    let cell_width = (max_number - 1).to_string().len();
+
+    pool.shuffle(&mut rng);
+    let mut output = pool
+        .iter()
+        .map(|num| num.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+    output.push('\n');
+
+    for _ in 0..num_boards {
+        output.push('\n');
+        let cells: Vec<u32> = pool
+            .choose_multiple(&mut rng, size * size)
+            .copied()
+            .collect();
+        for row in cells.chunks(size) {
+            let row_text = row
+                .iter()
+                .map(|num| format!("{:>width$}", num, width = cell_width))
+                .collect::<Vec<String>>()
+                .join(" ");
+            output.push_str(&row_text);
+            output.push('\n');
+        }
+    }
+
+    output
 }
 
 enum BingoVariant {
     Part1,
     Part2,
+    Timeline,
+    Analysis,
 }
 
 fn run_bingo(variant: BingoVariant) {
@@ -144,47 +432,66 @@ fn run_bingo(variant: BingoVariant) {
     let reader = BufReader::new(file);
 
     let (bingo_nums, mut boards) = parse_input(reader);
-
-    let mut unmarked_count: Option<u32> = None;
-    let mut last_entry: Option<u32> = None;
-
-    let mut winners: HashSet<usize> = HashSet::new();
-
-    let num_boards = boards.len();
-
-    'outer: for num in &bingo_nums {
-        for (i, board) in boards.iter_mut().enumerate() {
-            if !winners.contains(&i) {
-                board.try_mark_entry(*num);
-                if board.is_entry_winner(*num) {
-                    winners.insert(i);
-                    // Part 1 and 2 differ only by choosing either the first winning board, or last one
-                    let found_solution = match &variant {
-                        BingoVariant::Part1 => true,
-                        BingoVariant::Part2 => winners.len() == num_boards,
-                    };
-
-                    if found_solution {
-                        unmarked_count = Some(board.count_unmarked());
-                        last_entry = Some(*num);
-                        break 'outer;
-                    }
-                }
+    let rules = BingoRules::from_options();
+
+    let game = match variant {
+        BingoVariant::Analysis => analyze_bingo(&bingo_nums, &boards, &rules),
+        _ => play_bingo(&bingo_nums, &mut boards, &rules),
+    };
+
+    // Part 1 and 2 differ only by choosing either the first winning board, or last one
+    let winner = match variant {
+        BingoVariant::Part1 => game.winners.first(),
+        BingoVariant::Part2 => {
+            if game.never_won.is_empty() {
+                game.winners.last()
+            } else {
+                None
             }
         }
-    }
+        BingoVariant::Timeline => {
+            print_timeline(&game);
+            // -o winner=N picks the Nth board to win, starting at 1
+            let place: usize = options::get_or("winner", 1);
+            place.checked_sub(1).and_then(|i| game.winners.get(i))
+        }
+        BingoVariant::Analysis => {
+            if let (Some(best), Some(worst)) = (game.winners.first(), game.winners.last()) {
+                println!(
+                    "Best board - {} wins on turn {} with score {}",
+                    best.board,
+                    best.turn + 1,
+                    best.score()
+                );
+                println!(
+                    "Worst board - {} wins on turn {} with score {}",
+                    worst.board,
+                    worst.turn + 1,
+                    worst.score()
+                );
+            }
 
-    if unmarked_count.is_none() {
-        println!("No bingo boards won with the current input");
-    } else {
-        let unmarked_count = unmarked_count.unwrap();
-        let last_entry = last_entry.unwrap();
+            if !game.never_won.is_empty() {
+                println!("Boards that never win - {:?}", game.never_won);
+            }
 
-        println!(
-            "Last number called - {}, unmarked count - {}",
-            last_entry, unmarked_count
-        );
-        println!("Answer - {}", last_entry * unmarked_count);
+            print_ties(&game);
+
+            game.winners.first()
+        }
+    };
+
+    match winner {
+        Some(win) => {
+            println!(
+                "Last number called - {}, unmarked count - {}",
+                win.number, win.unmarked_count
+            );
+            println!("Answer - {}", win.score());
+        }
+        None => {
+            println!("No bingo boards won with the current input");
+        }
     }
 }
 
@@ -195,3 +502,60 @@ pub fn part1() {
 pub fn part2() {
     run_bingo(BingoVariant::Part2);
 }
+
+pub fn part3() {
+    run_bingo(BingoVariant::Timeline);
+}
+
+pub fn part4() {
+    run_bingo(BingoVariant::Analysis);
+}
+
+/**
+ * Generates a bingo input. Options: seed, boards, size, numbers (the draw pool size),
+ * out (file to write, otherwise printed) and unique (keep trying seeds until the first
+ * and last winners are untied).
+ */
+pub fn part5() {
+    let mut seed: u64 = options::get_or("seed", 0);
+    let num_boards: usize = options::get_or("boards", GENERATOR_BOARDS);
+    let size: usize = options::get_or("size", GENERATOR_BOARD_SIZE);
+    let max_number: u32 = options::get_or("numbers", GENERATOR_NUMBERS);
+    let require_unique = options::flag("unique");
+    let rules = BingoRules::from_options();
+
+    let mut attempts = 0;
+    let (text, game) = loop {
+        let text = generate_game(seed, num_boards, size, max_number);
+        let (bingo_nums, boards) = parse_input(BufReader::new(text.as_bytes()));
+        let game = analyze_bingo(&bingo_nums, &boards, &rules);
+
+        attempts += 1;
+        if !require_unique || has_unique_answers(&game) || attempts >= GENERATOR_MAX_ATTEMPTS {
+            break (text, game);
+        }
+
+        seed += 1;
+    };
+
+    match options::get::<String>("out") {
+        Some(path) => {
+            let mut file = File::create(&path).unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+            println!(
+                "Wrote {} boards of {}x{} to {}",
+                num_boards, size, size, path
+            );
+        }
+        None => print!("{}", text),
+    }
+
+    println!("Seed - {}", seed);
+    print_ties(&game);
+
+    if has_unique_answers(&game) {
+        println!("First and last winners are unique");
+    } else {
+        println!("First or last winner is ambiguous");
+    }
+}
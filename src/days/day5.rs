@@ -1,10 +1,19 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
 use lazy_static::lazy_static;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use regex::Regex;
 
+use crate::options;
+
+const DEFAULT_OVERLAP_THRESHOLD: u32 = 2;
+
+// Bounding boxes up to this many cells are counted in a dense grid instead of a hash map
+const DENSE_GRID_MAX_AREA: i64 = 1 << 24;
+
 enum QVariant {
     Part1,
     Part2,
@@ -27,37 +36,118 @@ struct Line {
     end: Point,
 }
 
+/**
+ * How a line is turned into grid points.
+ * Lattice only visits the points exactly on the line, stepping by the gcd reduced slope.
+ * Pixels treats every point as a unit square centred on it, and visits every square
+ * the line touches, including both sides of any corner it passes exactly through.
+ */
+#[derive(Debug, Copy, Clone)]
+enum Rasterisation {
+    Lattice,
+    Pixels,
+}
+
+impl Rasterisation {
+    fn from_options() -> Rasterisation {
+        match options::get::<String>("raster").as_deref() {
+            None | Some("lattice") => Rasterisation::Lattice,
+            Some("pixels") => Rasterisation::Pixels,
+            Some(other) => panic!("Unknown rasterisation '{}'", other),
+        }
+    }
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
 impl Line {
     fn is_diagonal(&self) -> bool {
         self.end.1 != self.start.1 && self.end.0 != self.start.0
     }
 
+    fn is_degenerate(&self) -> bool {
+        self.start == self.end
+    }
+
     /**
-     * Get the smallest integer unit for the slope.
-     * For this problem, it will be some combination of (0,1), (1,0), or (1,1),
-     * with different negative signs for direction
+     * Get the smallest integer step that stays on the line.
+     * Dividing by the gcd gives (0,1), (1,0), or (1,1) for straight and 45 degree lines,
+     * and e.g. (2,1) for a line from 0,0 -> 6,3
      */
     fn slope_unit(&self) -> Point {
-        // Since all slopes are either straight, or diagonal, we can cheat a little
-        // when normalizing
         let x_diff = self.end.0 - self.start.0;
-        let x = if x_diff == 0 {
-            0
-        } else if x_diff < 0 {
-            -1
-        } else {
-            1
-        };
         let y_diff = self.end.1 - self.start.1;
-        let y = if y_diff == 0 {
-            0
-        } else if y_diff < 0 {
-            -1
-        } else {
-            1
-        };
+        let divisor = gcd(x_diff, y_diff);
+
+        Point(x_diff / divisor, y_diff / divisor)
+    }
+
+    fn lattice_points(&self) -> Vec<Point> {
+        let unit = self.slope_unit();
+
+        let mut points = vec![];
+        let mut cur_point = self.start;
+        while cur_point != self.end {
+            points.push(cur_point);
+            cur_point.0 += unit.0;
+            cur_point.1 += unit.1;
+        }
+        // Gotta count the last one
+        points.push(cur_point);
+
+        points
+    }
+
+    /**
+     * Walks the cells the line passes through, one unit step at a time.
+     * Crossing the next vertical cell edge is at (2 * ix + 1) / (2 * nx) of the way along,
+     * and the next horizontal edge at (2 * iy + 1) / (2 * ny), so comparing the cross
+     * multiplied fractions picks which edge comes first without any floats.
+     */
+    fn pixel_points(&self) -> Vec<Point> {
+        let nx = (self.end.0 - self.start.0).abs();
+        let ny = (self.end.1 - self.start.1).abs();
+        let step_x = if self.start.0 < self.end.0 { 1 } else { -1 };
+        let step_y = if self.start.1 < self.end.1 { 1 } else { -1 };
+
+        let mut points = vec![self.start];
+        let mut cur_point = self.start;
+        let (mut ix, mut iy) = (0, 0);
+        while ix < nx || iy < ny {
+            let x_edge = (2 * ix + 1) * ny;
+            let y_edge = (2 * iy + 1) * nx;
+            if x_edge < y_edge {
+                cur_point.0 += step_x;
+                ix += 1;
+            } else if y_edge < x_edge {
+                cur_point.1 += step_y;
+                iy += 1;
+            } else {
+                // Straight through a corner, which touches the cells on both sides of it
+                points.push(Point(cur_point.0 + step_x, cur_point.1));
+                points.push(Point(cur_point.0, cur_point.1 + step_y));
+                cur_point.0 += step_x;
+                cur_point.1 += step_y;
+                ix += 1;
+                iy += 1;
+            }
+            points.push(cur_point);
+        }
 
-        Point(x, y)
+        points
+    }
+
+    fn points(&self, rasterisation: Rasterisation) -> Vec<Point> {
+        match rasterisation {
+            Rasterisation::Lattice => self.lattice_points(),
+            Rasterisation::Pixels => self.pixel_points(),
+        }
     }
 }
 
@@ -65,24 +155,411 @@ fn convert_to_point(x: &str, y: &str) -> Point {
     Point(x.parse::<i32>().unwrap(), y.parse::<i32>().unwrap())
 }
 
-fn fill_board(lines: &Vec<Line>) -> HashMap<Point, u32> {
-    let mut map = HashMap::new();
+/**
+ * Coverage count for every point touched by a line.
+ * Small vent fields use a flat grid over the bounding box, anything larger a hash map.
+ */
+#[derive(Debug)]
+enum VentMap {
+    Dense {
+        min: Point,
+        width: usize,
+        height: usize,
+        counts: Vec<u32>,
+    },
+    Sparse(HashMap<Point, u32>),
+}
+
+impl VentMap {
+    fn get(&self, point: Point) -> u32 {
+        match self {
+            VentMap::Dense {
+                min,
+                width,
+                height,
+                counts,
+            } => {
+                let x = (point.0 - min.0) as usize;
+                let y = (point.1 - min.1) as usize;
+                if point.0 < min.0 || point.1 < min.1 || x >= *width || y >= *height {
+                    0
+                } else {
+                    counts[y * width + x]
+                }
+            }
+            VentMap::Sparse(map) => *map.get(&point).unwrap_or(&0),
+        }
+    }
+
+    fn count_at_least(&self, threshold: u32) -> u64 {
+        let count = match self {
+            VentMap::Dense { counts, .. } => counts.iter().filter(|v| **v >= threshold).count(),
+            VentMap::Sparse(map) => map.values().filter(|v| **v >= threshold).count(),
+        };
+
+        count as u64
+    }
+}
+
+fn bounding_box(lines: &[Line]) -> (Point, Point) {
+    let mut min = Point(i32::MAX, i32::MAX);
+    let mut max = Point(i32::MIN, i32::MIN);
     for line in lines {
+        for point in [line.start, line.end] {
+            min = Point(min.0.min(point.0), min.1.min(point.1));
+            max = Point(max.0.max(point.0), max.1.max(point.1));
+        }
+    }
+
+    (min, max)
+}
+
+fn fill_board(lines: &[Line], rasterisation: Rasterisation) -> VentMap {
+    if lines.is_empty() {
+        return VentMap::Sparse(HashMap::new());
+    }
+
+    let (min, max) = bounding_box(lines);
+    let width = (max.0 as i64 - min.0 as i64 + 1) as usize;
+    let height = (max.1 as i64 - min.1 as i64 + 1) as usize;
+
+    if (width as i64) * (height as i64) <= DENSE_GRID_MAX_AREA {
+        let mut counts = vec![0; width * height];
+        for line in lines {
+            for point in line.points(rasterisation) {
+                let x = (point.0 - min.0) as usize;
+                let y = (point.1 - min.1) as usize;
+                counts[y * width + x] += 1;
+            }
+        }
+
+        VentMap::Dense {
+            min,
+            width,
+            height,
+            counts,
+        }
+    } else {
+        let mut map = HashMap::new();
+        for line in lines {
+            for point in line.points(rasterisation) {
+                *map.entry(point).or_insert(0) += 1;
+            }
+        }
+
+        VentMap::Sparse(map)
+    }
+}
+
+/**
+ * The diagram from the puzzle: '.' for no lines, otherwise the count.
+ * Counts past 9 don't fit in one character and are drawn as '#'.
+ * Always starts at 0,0 like the puzzle, even if no line gets close to it.
+ */
+fn render_diagram(map: &VentMap, lines: &[Line]) -> String {
+    let (_, max) = bounding_box(lines);
+    let mut output = String::new();
+    for y in 0..=max.1 {
+        for x in 0..=max.0 {
+            output.push(match map.get(Point(x, y)) {
+                0 => '.',
+                count @ 1..=9 => char::from_digit(count, 10).unwrap(),
+                _ => '#',
+            });
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+/**
+ * Heatmap of the counts over the bounding box, scaled so the busiest point is brightest.
+ * .pgm files are written as greyscale, anything else as a colour PPM going
+ * from dark blue through red to yellow.
+ */
+fn write_heatmap(map: &VentMap, lines: &[Line], path: &str) {
+    let (min, max) = bounding_box(lines);
+    let width = (max.0 - min.0 + 1) as usize;
+    let height = (max.1 - min.1 + 1) as usize;
+    let greyscale = path.ends_with(".pgm");
+
+    let mut peak = 1;
+    for y in min.1..=max.1 {
+        for x in min.0..=max.0 {
+            peak = peak.max(map.get(Point(x, y)));
+        }
+    }
+
+    let mut data = vec![];
+    let header = if greyscale { "P5" } else { "P6" };
+    data.extend(format!("{}\n{} {}\n255\n", header, width, height).bytes());
+
+    for y in min.1..=max.1 {
+        for x in min.0..=max.0 {
+            let level = map.get(Point(x, y)) as f64 / peak as f64;
+            if greyscale {
+                data.push((level * 255.0) as u8);
+            } else {
+                data.extend(heat_colour(level));
+            }
+        }
+    }
+
+    let mut file = File::create(path).unwrap();
+    file.write_all(&data).unwrap();
+    println!("Wrote {}x{} heatmap to {}", width, height, path);
+}
+
+fn heat_colour(level: f64) -> [u8; 3] {
+    if level <= 0.0 {
+        return [0, 0, 32];
+    }
+
+    let red = (level * 2.0).min(1.0);
+    let green = (level * 2.0 - 1.0).max(0.0);
+    [(red * 255.0) as u8, (green * 255.0) as u8, 64]
+}
+
+/*
+    Overlap counting without visiting every point.
+
+    Lines are grouped into collinear families (same reduced direction and same offset).
+    Within a family, coverage only changes at segment ends, so a 1D sweep over the
+    segments gives the number of points covered at least k times by that family alone.
+
+    Any point covered by two different families is a crossing, and there are at most one
+    per pair of lines. Those are found with a sweep over x for horizontal/vertical pairs,
+    and by directly intersecting every pair involving a sloped line. Crossing points get
+    their coverage from the segments that meet there, and are taken out of the family counts.
+*/
+#[derive(Debug, Hash, PartialEq, Eq, Copy, Clone)]
+struct FamilyKey {
+    direction: (i64, i64),
+    offset: i64,
+}
+
+#[derive(Debug)]
+struct SweepSegment {
+    start: (i64, i64),
+    direction: (i64, i64),
+    // Number of steps of `direction` from start to end
+    steps: i64,
+    family: FamilyKey,
+}
+
+impl SweepSegment {
+    fn from_line(line: &Line) -> SweepSegment {
         let unit = line.slope_unit();
+        let (mut start, mut end) = (line.start, line.end);
+        let mut direction = (unit.0 as i64, unit.1 as i64);
 
-        let mut cur_point = line.start;
-        while cur_point != line.end {
-            let count = map.entry(cur_point).or_insert(0);
-            *count += 1;
-            cur_point.0 += unit.0;
-            cur_point.1 += unit.1;
+        // Point every segment of a family the same way, so their positions line up
+        if direction.0 < 0 || (direction.0 == 0 && direction.1 < 0) {
+            std::mem::swap(&mut start, &mut end);
+            direction = (-direction.0, -direction.1);
+        }
+
+        let start = (start.0 as i64, start.1 as i64);
+        let steps = if direction.0 != 0 {
+            (end.0 as i64 - start.0) / direction.0
+        } else {
+            (end.1 as i64 - start.1) / direction.1
+        };
+
+        SweepSegment {
+            start,
+            direction,
+            steps,
+            family: FamilyKey {
+                direction,
+                offset: cross(direction, start),
+            },
+        }
+    }
+
+    fn end(&self) -> (i64, i64) {
+        (
+            self.start.0 + self.direction.0 * self.steps,
+            self.start.1 + self.direction.1 * self.steps,
+        )
+    }
+
+    fn is_horizontal(&self) -> bool {
+        self.direction == (1, 0)
+    }
+
+    fn is_vertical(&self) -> bool {
+        self.direction == (0, 1)
+    }
+
+    /**
+     * Position along the family's direction, in steps. Points of a family are a whole number
+     * of steps apart, so p.d changes by exactly d.d per step. Rounding down rather than
+     * towards zero keeps that true on both sides of the origin, e.g. for anti-diagonals
+     * crossing x = y.
+     */
+    fn position(&self, point: (i64, i64)) -> i64 {
+        let d = self.direction;
+        (point.0 * d.0 + point.1 * d.1).div_euclid(d.0 * d.0 + d.1 * d.1)
+    }
+}
+
+fn cross(a: (i64, i64), b: (i64, i64)) -> i64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+/**
+ * The lattice point where two non-parallel segments meet, if any
+ */
+fn intersect(a: &SweepSegment, b: &SweepSegment) -> Option<(i64, i64)> {
+    let denom = cross(a.direction, b.direction);
+    if denom == 0 {
+        return None;
+    }
+
+    let diff = (b.start.0 - a.start.0, b.start.1 - a.start.1);
+    let t_num = cross(diff, b.direction);
+    let s_num = cross(diff, a.direction);
+    if t_num % denom != 0 || s_num % denom != 0 {
+        return None;
+    }
+
+    let (t, s) = (t_num / denom, s_num / denom);
+    if t < 0 || t > a.steps || s < 0 || s > b.steps {
+        return None;
+    }
+
+    Some((a.start.0 + a.direction.0 * t, a.start.1 + a.direction.1 * t))
+}
+
+/**
+ * Finds all crossings, recording which segments meet at each one
+ */
+fn find_crossings(segments: &[SweepSegment]) -> HashMap<(i64, i64), HashSet<usize>> {
+    let mut crossings: HashMap<(i64, i64), HashSet<usize>> = HashMap::new();
+    let mut record = |point: (i64, i64), a: usize, b: usize| {
+        let entry = crossings.entry(point).or_default();
+        entry.insert(a);
+        entry.insert(b);
+    };
+
+    // Sweep over x. Horizontal segments are active between their ends, and each vertical
+    // segment looks up the active rows in its y range. At the same x, adds come before
+    // queries and removes after, so touching ends still count.
+    let mut events: Vec<(i64, u8, usize)> = vec![];
+    for (i, segment) in segments.iter().enumerate() {
+        if segment.is_horizontal() {
+            events.push((segment.start.0, 0, i));
+            events.push((segment.end().0, 2, i));
+        } else if segment.is_vertical() {
+            events.push((segment.start.0, 1, i));
+        }
+    }
+    events.sort();
+
+    let mut active: BTreeMap<i64, HashSet<usize>> = BTreeMap::new();
+    for (x, kind, i) in events {
+        let segment = &segments[i];
+        match kind {
+            0 => {
+                active.entry(segment.start.1).or_default().insert(i);
+            }
+            1 => {
+                for (y, rows) in active.range(segment.start.1..=segment.end().1) {
+                    for row in rows {
+                        record((x, *y), i, *row);
+                    }
+                }
+            }
+            _ => {
+                let row = active.get_mut(&segment.start.1).unwrap();
+                row.remove(&i);
+                if row.is_empty() {
+                    active.remove(&segment.start.1);
+                }
+            }
+        }
+    }
+
+    // Everything else has at least one sloped segment, so check those pairs directly
+    for (i, a) in segments.iter().enumerate() {
+        if a.is_horizontal() || a.is_vertical() {
+            continue;
+        }
+
+        for (j, b) in segments.iter().enumerate() {
+            if i == j || (!b.is_horizontal() && !b.is_vertical() && j < i) {
+                continue;
+            }
+
+            if let Some(point) = intersect(a, b) {
+                record(point, i, j);
+            }
+        }
+    }
+
+    crossings
+}
+
+/**
+ * Number of positions in a family covered by at least `threshold` of its segments
+ */
+fn count_family_overlaps(segments: &[&SweepSegment], threshold: u32) -> u64 {
+    let mut events: Vec<(i64, i32)> = vec![];
+    for segment in segments {
+        let start = segment.position(segment.start);
+        events.push((start, 1));
+        events.push((start + segment.steps + 1, -1));
+    }
+    events.sort();
+
+    let mut count = 0;
+    let mut coverage: i32 = 0;
+    for (i, (position, delta)) in events.iter().enumerate() {
+        coverage += delta;
+        if let Some((next, _)) = events.get(i + 1) {
+            if coverage >= threshold as i32 {
+                count += (next - position) as u64;
+            }
+        }
+    }
+
+    count
+}
+
+fn count_overlaps_sweep(lines: &[Line], threshold: u32) -> u64 {
+    let segments: Vec<SweepSegment> = lines.iter().map(SweepSegment::from_line).collect();
+
+    let mut families: HashMap<FamilyKey, Vec<&SweepSegment>> = HashMap::new();
+    for segment in &segments {
+        families.entry(segment.family).or_default().push(segment);
+    }
+
+    let mut count: u64 = families
+        .values()
+        .map(|family| count_family_overlaps(family, threshold))
+        .sum();
+
+    for segment_ids in find_crossings(&segments).values() {
+        let mut family_coverage: HashMap<FamilyKey, u32> = HashMap::new();
+        for i in segment_ids {
+            *family_coverage.entry(segments[*i].family).or_insert(0) += 1;
+        }
+
+        // This point was already counted once for every family reaching the threshold on its own
+        let counted = family_coverage
+            .values()
+            .filter(|c| **c >= threshold)
+            .count() as u64;
+        count -= counted;
+
+        if segment_ids.len() as u32 >= threshold {
+            count += 1;
         }
-        // Gotta count the last one
-        let count = map.entry(cur_point).or_insert(0);
-        *count += 1;
     }
 
-    map
+    count
 }
 
 fn parse_input(reader: BufReader<File>) -> Vec<Line> {
@@ -100,10 +577,16 @@ fn parse_input(reader: BufReader<File>) -> Vec<Line> {
         let captures = INPUT_RE.captures_iter(text.as_str());
 
         for cap in captures {
-            lines.push(Line {
+            let line = Line {
                 start: convert_to_point(&cap[1], &cap[2]),
                 end: convert_to_point(&cap[3], &cap[4]),
-            });
+            };
+
+            if line.is_degenerate() {
+                panic!("Line '{}' has zero length", text);
+            }
+
+            lines.push(line);
         }
     }
 
@@ -120,13 +603,132 @@ fn run_problem(variant: QVariant) {
         lines.retain(|l| !l.is_diagonal());
     }
 
-    // Fill a hashmap of points. Then, filter to just the values >= 2
-    let map = fill_board(&lines);
-    let count = map.values().filter(|v| **v >= 2).count();
+    let threshold: u32 = options::get_or("threshold", DEFAULT_OVERLAP_THRESHOLD);
+    if threshold == 0 {
+        panic!("Overlap threshold must be at least 1");
+    }
+    let rasterisation = Rasterisation::from_options();
+
+    // The sweep relies on exact lattice points, so pixel lines always go through the map
+    let use_map = options::get::<String>("method").as_deref() == Some("map")
+        || matches!(rasterisation, Rasterisation::Pixels);
+
+    let diagram_path = options::get::<String>("diagram");
+    let heatmap_path = options::get::<String>("heatmap");
+
+    let map = if use_map || diagram_path.is_some() || heatmap_path.is_some() {
+        Some(fill_board(&lines, rasterisation))
+    } else {
+        None
+    };
+
+    if let Some(map) = &map {
+        if let Some(path) = diagram_path {
+            let mut file = File::create(&path).unwrap();
+            file.write_all(render_diagram(map, &lines).as_bytes())
+                .unwrap();
+            println!("Wrote diagram to {}", path);
+        }
+
+        if let Some(path) = heatmap_path {
+            write_heatmap(map, &lines, &path);
+        }
+    }
+
+    let count = match &map {
+        Some(map) if use_map => map.count_at_least(threshold),
+        _ => count_overlaps_sweep(&lines, threshold),
+    };
+
+    // -o verify counts again with the other method to check the answer
+    if options::flag("verify") && matches!(rasterisation, Rasterisation::Lattice) {
+        let other = if use_map {
+            count_overlaps_sweep(&lines, threshold)
+        } else {
+            fill_board(&lines, rasterisation).count_at_least(threshold)
+        };
+        assert_eq!(count, other, "The sweep and the map disagree");
+        println!("Verified against the other method");
+    }
 
     println!("Answer - {}", count);
 }
 
+const CHECK_SEED: u64 = 2021;
+const CHECK_CASES: usize = 2000;
+const CHECK_GRID: i32 = 40;
+
+/**
+ * Random lines in a small grid, so they overlap and cross a lot. Directions cover
+ * horizontal, vertical, both diagonals and a few steeper slopes.
+ */
+fn generate_lines(rng: &mut StdRng, count: usize) -> Vec<Line> {
+    const DIRECTIONS: [(i32, i32); 8] = [
+        (1, 0),
+        (0, 1),
+        (1, 1),
+        (1, -1),
+        (1, 2),
+        (2, -1),
+        (-1, 3),
+        (3, 1),
+    ];
+
+    let mut lines = vec![];
+    while lines.len() < count {
+        let direction = DIRECTIONS[rng.gen_range(0..DIRECTIONS.len())];
+        let steps = rng.gen_range(1..10);
+        let start = Point(rng.gen_range(0..CHECK_GRID), rng.gen_range(0..CHECK_GRID));
+        let end = Point(start.0 + direction.0 * steps, start.1 + direction.1 * steps);
+
+        if (0..CHECK_GRID).contains(&end.0) && (0..CHECK_GRID).contains(&end.1) {
+            lines.push(Line { start, end });
+        }
+    }
+
+    lines
+}
+
+/**
+ * Checks the sweep against filling the map, on some hand picked cases and then on
+ * random ones, for every threshold from 1 to 3
+ */
+fn check_sweep() {
+    let line = |x1, y1, x2, y2| Line {
+        start: Point(x1, y1),
+        end: Point(x2, y2),
+    };
+
+    // Anti-diagonals starting on opposite sides of x = y
+    let mut cases = vec![
+        vec![line(0, 5, 3, 2), line(3, 2, 5, 0)],
+        vec![line(0, 5, 2, 3), line(3, 2, 5, 0)],
+        vec![line(0, 3, 3, 0), line(1, 2, 2, 1), line(4, 7, 7, 4)],
+    ];
+
+    let mut rng = StdRng::seed_from_u64(CHECK_SEED);
+    for _ in 0..CHECK_CASES {
+        let count = rng.gen_range(1..30);
+        cases.push(generate_lines(&mut rng, count));
+    }
+
+    for lines in &cases {
+        let map = fill_board(lines, Rasterisation::Lattice);
+        for threshold in 1..=3 {
+            let expected = map.count_at_least(threshold);
+            let found = count_overlaps_sweep(lines, threshold);
+            if found != expected {
+                panic!(
+                    "Sweep counted {} but the map counted {} at threshold {} for {:?}",
+                    found, expected, threshold, lines
+                );
+            }
+        }
+    }
+
+    println!("Sweep matches the map on {} cases", cases.len());
+}
+
 pub fn part1() {
     run_problem(QVariant::Part1);
 }
@@ -134,3 +736,7 @@ pub fn part1() {
 pub fn part2() {
     run_problem(QVariant::Part2);
 }
+
+pub fn part3() {
+    check_sweep();
+}
@@ -1,48 +1,309 @@
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
+use crate::options;
+
 // Technically + 1 day due to 0 index
 const FISH_RESPAWN_DAYS: usize = 6 + 1;
 const FISH_NEW_DAYS: usize = 8 + 1;
-const MAX_FISH_GROUP: usize = 9;
 
 enum QVariant {
     Part1,
     Part2,
 }
 
+/**
+ * How long a fish takes to spawn again after spawning (respawn_days),
+ * and how long a newborn takes before its first spawn (new_days).
+ * Both include the day the timer sits at 0.
+ */
+#[derive(Debug, Copy, Clone)]
+struct Lifecycle {
+    respawn_days: usize,
+    new_days: usize,
+}
+
+impl Lifecycle {
+    fn from_options() -> Lifecycle {
+        let lifecycle = Lifecycle {
+            respawn_days: options::get_or("respawn", FISH_RESPAWN_DAYS),
+            new_days: options::get_or("newborn", FISH_NEW_DAYS),
+        };
+
+        if lifecycle.respawn_days == 0 || lifecycle.new_days == 0 {
+            panic!("Lifecycle periods must be at least 1 day");
+        }
+
+        lifecycle
+    }
+
+    // One bucket per timer value, big enough for both adult and newborn timers
+    fn num_groups(&self) -> usize {
+        self.respawn_days.max(self.new_days)
+    }
+}
+
+type Matrix = Vec<Vec<u128>>;
+
+/**
+ * Counts are either exact (panicking if they overflow u128),
+ * or reduced modulo some number so any day is reachable
+ */
+#[derive(Debug, Copy, Clone)]
+enum Arithmetic {
+    Exact,
+    Modulo(u128),
+}
+
+impl Arithmetic {
+    fn reduce(&self, value: u128) -> u128 {
+        match self {
+            Arithmetic::Exact => value,
+            Arithmetic::Modulo(m) => value % m,
+        }
+    }
+
+    fn add(&self, a: u128, b: u128) -> u128 {
+        let sum = a
+            .checked_add(b)
+            .expect("Fish count overflowed, try -o method=matrix -o modulus=...");
+        self.reduce(sum)
+    }
+
+    fn mul(&self, a: u128, b: u128) -> u128 {
+        let product = a
+            .checked_mul(b)
+            .expect("Fish count overflowed, try -o method=matrix -o modulus=...");
+        self.reduce(product)
+    }
+}
+
+/**
+ * One day of the lifecycle as a matrix over timer values: entry [to][from] is how many
+ * fish with timer `to` a single fish with timer `from` turns into.
+ */
+fn transition_matrix(lifecycle: &Lifecycle) -> Matrix {
+    let size = lifecycle.num_groups();
+    let mut matrix = vec![vec![0; size]; size];
+
+    for from in 1..size {
+        matrix[from - 1][from] = 1;
+    }
+
+    matrix[lifecycle.respawn_days - 1][0] += 1;
+    matrix[lifecycle.new_days - 1][0] += 1;
+
+    matrix
+}
+
+fn multiply(a: &Matrix, b: &Matrix, arithmetic: &Arithmetic) -> Matrix {
+    let size = a.len();
+    let mut result = vec![vec![0; size]; size];
+    for i in 0..size {
+        for k in 0..size {
+            if a[i][k] == 0 {
+                continue;
+            }
+
+            for j in 0..size {
+                let term = arithmetic.mul(a[i][k], b[k][j]);
+                result[i][j] = arithmetic.add(result[i][j], term);
+            }
+        }
+    }
+
+    result
+}
+
+/**
+ * Jumps straight to the given day by raising the transition matrix to that power,
+ * using exponentiation by squaring for O(log days) multiplications
+ */
+fn fast_forward(
+    fish_by_timer: &[u128],
+    lifecycle: &Lifecycle,
+    num_days: usize,
+    arithmetic: &Arithmetic,
+) -> u128 {
+    let size = lifecycle.num_groups();
+    let mut power = transition_matrix(lifecycle);
+    let mut result: Matrix = (0..size)
+        .map(|i| (0..size).map(|j| if i == j { 1 } else { 0 }).collect())
+        .collect();
+
+    let mut remaining = num_days;
+    while remaining > 0 {
+        if remaining & 1 == 1 {
+            result = multiply(&result, &power, arithmetic);
+        }
+
+        remaining >>= 1;
+        if remaining > 0 {
+            power = multiply(&power, &power, arithmetic);
+        }
+    }
+
+    let mut total = 0;
+    for row in &result {
+        for (from, count) in fish_by_timer.iter().enumerate() {
+            let term = arithmetic.mul(row[from], arithmetic.reduce(*count));
+            total = arithmetic.add(total, term);
+        }
+    }
+
+    total
+}
+
+/**
+ * Long run growth per day, the dominant eigenvalue of the transition matrix.
+ * Found by power iteration. Every timer eventually reaches every other, but that's
+ * not enough to converge: with e.g. equal respawn and newborn periods the population
+ * cycles with that period, and the ratio between days never settles. Iterating on M + I
+ * instead breaks the cycle without moving the eigenvectors, and its dominant eigenvalue
+ * is exactly one more than M's. None if it still hasn't converged.
+ */
+fn dominant_eigenvalue(lifecycle: &Lifecycle) -> Option<f64> {
+    let matrix = transition_matrix(lifecycle);
+    let size = matrix.len();
+
+    let shifted = |i: usize, j: usize| matrix[i][j] as f64 + if i == j { 1.0 } else { 0.0 };
+
+    let mut vector = vec![1.0; size];
+    let mut eigenvalue = 0.0;
+    for _ in 0..100_000 {
+        let next: Vec<f64> = (0..size)
+            .map(|i| (0..size).map(|j| shifted(i, j) * vector[j]).sum())
+            .collect();
+        let norm: f64 = next.iter().sum();
+        let next: Vec<f64> = next.iter().map(|v| v / norm).collect();
+
+        let converged = (norm - eigenvalue).abs() < 1e-12;
+        eigenvalue = norm;
+        vector = next;
+        if converged {
+            return Some(eigenvalue - 1.0);
+        }
+    }
+
+    None
+}
+
+/**
+ * CSV row for the current day. The ring buffer is rotated back so column t
+ * is always the number of fish with timer t.
+ */
+fn csv_row(day: usize, fish_group: &[u128], previous_total: Option<u128>) -> String {
+    let size = fish_group.len();
+    let total = population(fish_group);
+    let growth = match previous_total {
+        Some(prev) if prev > 0 => format!("{:.6}", total as f64 / prev as f64),
+        _ => String::new(),
+    };
+
+    let histogram = (0..size)
+        .map(|timer| fish_group[(day + timer) % size].to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+
+    format!("{},{},{},{}\n", day, total, histogram, growth)
+}
+
+// Total across the buckets, panicking with the same hint as Arithmetic if it overflows
+fn population(fish_group: &[u128]) -> u128 {
+    fish_group
+        .iter()
+        .fold(0, |total, count| Arithmetic::Exact.add(total, *count))
+}
+
 fn run_problem(variant: QVariant) {
     let file = File::open("inputs/day6/input.txt").unwrap();
     let reader = BufReader::new(file);
 
+    let lifecycle = Lifecycle::from_options();
+    let max_fish_group = lifecycle.num_groups();
+
     let starter_fishes: Vec<usize> = reader
         .lines()
         .next()
         .unwrap()
         .unwrap()
-        .split(",")
-        .map(|x| x.parse::<usize>().unwrap())
+        .split(',')
+        .map(|x| x.trim().parse::<usize>().unwrap())
         .collect();
 
-    let mut fish_group: [u64; 9] = [0; 9];
+    let mut fish_group: Vec<u128> = vec![0; max_fish_group];
     starter_fishes.iter().for_each(|fish| {
+        if *fish >= max_fish_group {
+            panic!(
+                "Fish timer {} doesn't fit a lifecycle of {} days",
+                fish, max_fish_group
+            );
+        }
         fish_group[*fish] += 1;
     });
 
-    let num_days = match variant {
+    let default_days = match variant {
         QVariant::Part1 => 80,
         QVariant::Part2 => 256,
     };
+    let num_days: usize = options::get_or("days", default_days);
+
+    if options::get::<String>("method").as_deref() == Some("matrix") {
+        // Capped to u64 so products of two reduced counts always fit in a u128
+        let arithmetic = match options::get::<u64>("modulus") {
+            Some(0) => panic!("Modulus must be at least 1"),
+            Some(m) => Arithmetic::Modulo(m as u128),
+            None => Arithmetic::Exact,
+        };
+
+        let total = fast_forward(&fish_group, &lifecycle, num_days, &arithmetic);
+        match arithmetic {
+            Arithmetic::Exact => println!("Answer - {}", total),
+            Arithmetic::Modulo(m) => println!("Answer - {} (mod {})", total, m),
+        }
+        return;
+    }
+
+    // -o csv=path writes the population and timer histogram for every day
+    let mut csv = options::get::<String>("csv").map(|path| {
+        let header = (0..max_fish_group)
+            .map(|timer| format!("timer_{}", timer))
+            .collect::<Vec<String>>()
+            .join(",");
+
+        let mut text = format!("day,population,{},growth_rate\n", header);
+        text.push_str(&csv_row(0, &fish_group, None));
+        (path, text)
+    });
 
     for i in 0..num_days {
-        let curr_day_0 = i % MAX_FISH_GROUP;
+        let previous_total = population(&fish_group);
+
+        let curr_day_0 = i % max_fish_group;
         let fish_refreshed = fish_group[curr_day_0];
         fish_group[curr_day_0] = 0;
-        fish_group[(curr_day_0 + FISH_RESPAWN_DAYS) % MAX_FISH_GROUP] += fish_refreshed;
-        fish_group[(curr_day_0 + FISH_NEW_DAYS) % MAX_FISH_GROUP] += fish_refreshed;
+        for days in [lifecycle.respawn_days, lifecycle.new_days] {
+            let group = (curr_day_0 + days) % max_fish_group;
+            fish_group[group] = Arithmetic::Exact.add(fish_group[group], fish_refreshed);
+        }
+
+        if let Some((_, text)) = &mut csv {
+            text.push_str(&csv_row(i + 1, &fish_group, Some(previous_total)));
+        }
+    }
+
+    if let Some((path, text)) = csv {
+        let mut file = File::create(&path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        println!("Wrote {} days to {}", num_days + 1, path);
+
+        match dominant_eigenvalue(&lifecycle) {
+            Some(eigenvalue) => println!("Dominant eigenvalue - {:.6} per day", eigenvalue),
+            None => println!("Dominant eigenvalue - power iteration did not converge"),
+        }
     }
 
-    println!("Answer - {}", fish_group.iter().sum::<u64>());
+    println!("Answer - {}", population(&fish_group));
 }
 
 pub fn part1() {
@@ -2,64 +2,315 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
+use crate::options;
+
 enum QVariant {
     Part1,
     Part2,
 }
 
+/**
+ * Fuel a crab burns to move a given distance. The optimiser assumes the cost is
+ * non-decreasing and convex in the distance, which makes the total over all crabs
+ * convex in the goal position.
+ */
+trait FuelCost {
+    fn name(&self) -> String;
+
+    fn cost(&self, distance: i64) -> i64;
+
+    fn total(&self, positions: &[i32], goal: i32) -> i64 {
+        positions
+            .iter()
+            .map(|pos| self.cost((goal - pos).abs() as i64))
+            .sum()
+    }
+
+    /**
+     * Best position and its fuel. Positions must be sorted.
+     * By default this binary searches for where the total stops decreasing,
+     * which works for any convex cost.
+     */
+    fn optimise(&self, sorted_positions: &[i32]) -> (i32, i64) {
+        let mut low = sorted_positions[0];
+        let mut high = *sorted_positions.last().unwrap();
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.total(sorted_positions, mid) <= self.total(sorted_positions, mid + 1) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        (low, self.total(sorted_positions, low))
+    }
+}
+
+struct LinearCost;
+
+impl FuelCost for LinearCost {
+    fn name(&self) -> String {
+        "linear".to_string()
+    }
+
+    fn cost(&self, distance: i64) -> i64 {
+        distance
+    }
+
+    // The sum of distances is minimised by the median
+    fn optimise(&self, sorted_positions: &[i32]) -> (i32, i64) {
+        let median = sorted_positions[sorted_positions.len() / 2];
+        (median, self.total(sorted_positions, median))
+    }
+}
+
+struct TriangularCost;
+
+impl FuelCost for TriangularCost {
+    fn name(&self) -> String {
+        "triangular".to_string()
+    }
+
+    fn cost(&self, distance: i64) -> i64 {
+        (distance * distance + distance) / 2
+    }
+
+    /**
+     * (d^2 + d) / 2 totals are minimised within half a step of the mean,
+     * so checking the positions either side of the mean is enough
+     */
+    fn optimise(&self, sorted_positions: &[i32]) -> (i32, i64) {
+        let sum: i64 = sorted_positions.iter().map(|pos| *pos as i64).sum();
+        let floor = sum.div_euclid(sorted_positions.len() as i64) as i32;
+
+        [floor, floor + 1]
+            .iter()
+            .map(|goal| (*goal, self.total(sorted_positions, *goal)))
+            .min_by_key(|(_, fuel)| *fuel)
+            .unwrap()
+    }
+}
+
+struct QuadraticCost;
+
+impl FuelCost for QuadraticCost {
+    fn name(&self) -> String {
+        "quadratic".to_string()
+    }
+
+    fn cost(&self, distance: i64) -> i64 {
+        distance * distance
+    }
+}
+
+/**
+ * Piecewise linear cost, given as (distance, fuel per step) breakpoints sorted by distance.
+ * e.g. [(0, 1), (10, 3)] costs 1 per step for the first 10 steps and 3 per step after.
+ */
+struct PiecewiseCost {
+    pieces: Vec<(i64, i64)>,
+}
+
+impl PiecewiseCost {
+    /**
+     * Parses "distance:rate" pairs separated by '/', e.g. "0:1/10:3/50:10".
+     * Rates must not decrease, otherwise the cost isn't convex.
+     */
+    fn parse(text: &str) -> PiecewiseCost {
+        let mut pieces: Vec<(i64, i64)> = text
+            .split('/')
+            .map(|piece| {
+                let (distance, rate) = piece
+                    .split_once(':')
+                    .unwrap_or_else(|| panic!("Piece '{}' should be distance:rate", piece));
+                (
+                    distance.trim().parse::<i64>().unwrap(),
+                    rate.trim().parse::<i64>().unwrap(),
+                )
+            })
+            .collect();
+        pieces.sort_unstable();
+
+        if pieces.first().map(|p| p.0) != Some(0) {
+            panic!("Piecewise cost must start at distance 0");
+        }
+
+        for pair in pieces.windows(2) {
+            if pair[1].1 < pair[0].1 {
+                panic!("Piecewise cost rates must not decrease, otherwise it isn't convex");
+            }
+        }
+
+        PiecewiseCost { pieces }
+    }
+}
+
+impl FuelCost for PiecewiseCost {
+    fn name(&self) -> String {
+        let pieces = self
+            .pieces
+            .iter()
+            .map(|(distance, rate)| format!("{}:{}", distance, rate))
+            .collect::<Vec<String>>();
+        format!("piecewise {}", pieces.join("/"))
+    }
+
+    fn cost(&self, distance: i64) -> i64 {
+        let mut fuel = 0;
+        for (i, (start, rate)) in self.pieces.iter().enumerate() {
+            if distance <= *start {
+                break;
+            }
+
+            let end = self
+                .pieces
+                .get(i + 1)
+                .map_or(distance, |p| p.0.min(distance));
+            fuel += (end - start) * rate;
+        }
+
+        fuel
+    }
+}
+
+/**
+ * -o cost=linear|triangular|quadratic|piecewise picks the model, otherwise part 1 is linear
+ * and part 2 triangular. Piecewise costs are given with -o pieces=0:1/10:3
+ */
+fn cost_from_options(variant: &QVariant) -> Box<dyn FuelCost> {
+    let default_cost = match variant {
+        QVariant::Part1 => "linear",
+        QVariant::Part2 => "triangular",
+    };
+
+    match options::get_or("cost", default_cost.to_string()).as_str() {
+        "linear" => Box::new(LinearCost),
+        "triangular" => Box::new(TriangularCost),
+        "quadratic" => Box::new(QuadraticCost),
+        "piecewise" => {
+            let pieces: String = options::get("pieces").expect("Piecewise cost needs -o pieces=");
+            Box::new(PiecewiseCost::parse(&pieces))
+        }
+        other => panic!("Unknown fuel cost '{}'", other),
+    }
+}
+
 fn move_to(
-    positions: &Vec<i32>,
+    positions: &[i32],
     goal: i32,
-    variant: &QVariant,
-    lookup_map: &mut HashMap<i32, i32>,
-) -> u32 {
+    fuel_cost: &dyn FuelCost,
+    lookup_map: &mut HashMap<i64, i64>,
+) -> i64 {
     let mut fuel = 0;
 
     for pos in positions {
-        match variant {
-            QVariant::Part1 => fuel += (goal - *pos).abs(),
-            QVariant::Part2 => {
-                // micro optimization using lookup table.
-                // Without this, Debug builds take 20+ seconds, and release takes 0.7s
-                // Adding the lookup makes it run in 1.5s in Debug, and 0.1s in release
-                let diff = (goal - *pos).abs();
-                if lookup_map.contains_key(&diff) {
-                    fuel += lookup_map.get(&diff).unwrap();
-                } else {
-                    let total = ((diff + 1) * diff) / 2;
-                    fuel += total;
-                    lookup_map.insert(diff, total);
-                }
-            }
+        // micro optimization using lookup table.
+        // Without this, Debug builds take 20+ seconds, and release takes 0.7s
+        // Adding the lookup makes it run in 1.5s in Debug, and 0.1s in release
+        let diff = (goal - *pos).abs() as i64;
+        fuel += *lookup_map
+            .entry(diff)
+            .or_insert_with(|| fuel_cost.cost(diff));
+    }
+
+    fuel
+}
+
+// Tries every position between 0 and the furthest crab
+fn brute_force(positions: &[i32], fuel_cost: &dyn FuelCost) -> (i32, i64) {
+    let max = *positions.iter().max().unwrap();
+    let mut lookup_map: HashMap<i64, i64> = HashMap::new();
+
+    (0..max + 1)
+        .map(|i| (i, move_to(positions, i, fuel_cost, &mut lookup_map)))
+        .min_by_key(|(_, fuel)| *fuel)
+        .unwrap()
+}
+
+/**
+ * Linear and triangular fuel for every position from 0 to the furthest crab, in O(range + n).
+ *
+ * Sweeping the goal one step right moves every crab already behind it one step further.
+ * For linear cost that adds one per crab behind. For triangular cost, going from d to d + 1
+ * adds d + 1, so it grows by the linear total plus the crab count. A sweep from each side
+ * gives the costs of the crabs behind and ahead, which are summed per position.
+ */
+fn cost_curve(positions: &[i32]) -> Vec<(i64, i64)> {
+    let max = *positions.iter().max().unwrap() as usize;
+    let mut histogram = vec![0i64; max + 1];
+    for pos in positions {
+        histogram[*pos as usize] += 1;
+    }
+
+    let sweep = |order: &mut dyn Iterator<Item = usize>| -> Vec<(i64, i64)> {
+        let mut costs = vec![(0, 0); max + 1];
+        let (mut count, mut linear, mut triangular) = (0i64, 0i64, 0i64);
+        for goal in order {
+            // Everyone behind moves one step further, then pick up the crabs at this position
+            triangular += linear + count;
+            linear += count;
+            count += histogram[goal];
+            costs[goal] = (linear, triangular);
         }
+
+        costs
+    };
+
+    let from_left = sweep(&mut (0..=max));
+    let from_right = sweep(&mut (0..=max).rev());
+
+    from_left
+        .iter()
+        .zip(from_right.iter())
+        .map(|(left, right)| (left.0 + right.0, left.1 + right.1))
+        .collect()
+}
+
+fn write_cost_curve(positions: &[i32], path: &str) {
+    let mut text = String::from("position,linear,triangular\n");
+    for (goal, (linear, triangular)) in cost_curve(positions).iter().enumerate() {
+        text.push_str(&format!("{},{},{}\n", goal, linear, triangular));
     }
 
-    fuel as u32
+    let mut file = File::create(path).unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+    println!("Wrote cost curve to {}", path);
 }
 
 fn run_problem(variant: QVariant) {
     let file = File::open("inputs/day7/input.txt").unwrap();
     let reader = BufReader::new(file);
 
-    let positions: Vec<i32> = reader
+    let mut positions: Vec<i32> = reader
         .lines()
         .next()
         .unwrap()
         .unwrap()
-        .split(",")
-        .map(|x| x.parse::<i32>().unwrap())
+        .split(',')
+        .map(|x| x.trim().parse::<i32>().unwrap())
         .collect();
+    positions.sort_unstable();
 
-    let max = positions.iter().max().unwrap();
-    let mut lookup_map: HashMap<i32, i32> = HashMap::new();
-    let mut fuel_costs = vec![];
-    for i in 0..max + 1 {
-        fuel_costs.push(move_to(&positions, i, &variant, &mut lookup_map));
+    // -o curve=path dumps the linear and triangular fuel for every position
+    if let Some(path) = options::get::<String>("curve") {
+        write_cost_curve(&positions, &path);
     }
 
-    let min = fuel_costs.iter().min().unwrap();
+    let fuel_cost = cost_from_options(&variant);
+    let (position, fuel) = fuel_cost.optimise(&positions);
+
+    // -o verify re-runs the old brute force over every position to check the answer
+    if options::flag("verify") {
+        let (_, brute_fuel) = brute_force(&positions, fuel_cost.as_ref());
+        assert_eq!(fuel, brute_fuel, "Brute force found a cheaper position");
+        println!("Verified against brute force");
+    }
 
-    println!("Answer - {}", min);
+    println!("Cost - {}", fuel_cost.name());
+    println!("Position - {}", position);
+    println!("Answer - {}", fuel);
 }
 
 pub fn part1() {
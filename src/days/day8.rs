@@ -1,288 +1,1169 @@
-use itertools::zip;
-use itertools::Itertools;
-use lazy_static::lazy_static;
-use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::options;
 
 enum QVariant {
     Part1,
     Part2,
 }
 
-fn sort_string(s: &str) -> String {
-    let mut char_arr = s.chars().collect::<Vec<char>>();
-    char_arr.sort_by(|a, b| b.cmp(a));
-    String::from_iter(char_arr)
+/**
+ * Glyph for each pattern decoded so far, keyed by the pattern's wire bitmask.
+ * There are only as many entries as glyphs, so a linear scan beats hashing.
+ */
+#[derive(Debug, Default)]
+struct PatternMap {
+    entries: Vec<(u32, u32)>,
 }
 
-fn map_signals(signals: &Vec<&str>) -> HashMap<String, u32> {
-    let mut map = HashMap::new();
-    for signal in signals {
-        let sorted_signal = sort_string(signal);
-        match sorted_signal.len() {
-            2 => {
-                map.insert(sorted_signal, 1);
+impl PatternMap {
+    fn insert(&mut self, pattern: u32, glyph: u32) {
+        match self.entries.iter_mut().find(|(mask, _)| *mask == pattern) {
+            Some(entry) => entry.1 = glyph,
+            None => self.entries.push((pattern, glyph)),
+        }
+    }
+
+    fn get(&self, pattern: u32) -> Option<u32> {
+        self.entries
+            .iter()
+            .find(|(mask, _)| *mask == pattern)
+            .map(|(_, glyph)| *glyph)
+    }
+}
+
+/**
+ * Maps the patterns that can be recognised by length alone, like 1, 4, 7 and 8 for
+ * the seven segment digits. Those are the glyphs with a segment count no other glyph has.
+ */
+fn map_signals(patterns: &[u32], table: &GlyphTable) -> PatternMap {
+    let mut map = PatternMap::default();
+    for pattern in patterns {
+        let mut same_size = (0..table.masks.len())
+            .filter(|glyph| table.masks[*glyph].count_ones() == pattern.count_ones());
+
+        if let (Some(glyph), None) = (same_size.next(), same_size.next()) {
+            map.insert(*pattern, glyph as u32);
+        }
+    }
+
+    map
+}
+
+/*
+  If you treat every edge like a number, then each number has this mapping
+   0
+  1 2
+   3
+  4 5
+   6
+
+   0 - 0, 1, 2, 4, 5, 6
+   1 - 2, 5
+   2 - 0, 2, 3, 4, 6
+   3 - 0, 2, 3, 5, 6
+   4 - 1, 2, 3, 5
+   5 - 0, 1, 3, 5, 6
+   6 - 0, 1, 3, 4, 5, 6
+   7 - 0, 2, 5
+   8 - 0, 1, 2, 3, 4, 5, 6
+   9 - 0, 1, 2, 3, 5, 6
+
+  Stored as bitmasks, with bit n set for segment n
+*/
+const SEGMENT_FOR_NUM: [u32; 10] = [
+    0b1110111, 0b0100100, 0b1011101, 0b1101101, 0b0101110, 0b1101011, 0b1111011, 0b0100101,
+    0b1111111, 0b1101111,
+];
+
+/*
+   A - 0, 1, 2, 3, 4, 5
+   b - 1, 3, 4, 5, 6
+   C - 0, 1, 4, 6
+   d - 2, 3, 4, 5, 6
+   E - 0, 1, 3, 4, 6
+   F - 0, 1, 3, 4
+*/
+const SEGMENT_FOR_HEX_LETTER: [u32; 6] = [
+    0b0111111, 0b1111010, 0b1010011, 0b1111100, 0b1011011, 0b0011011,
+];
+
+const NUM_SEGMENTS: usize = 7;
+
+/**
+ * The symbols a display can show, and which segments light up for each.
+ * A glyph's index is also its value when a display is read as a number.
+ */
+#[derive(Debug)]
+struct GlyphTable {
+    num_segments: usize,
+    symbols: Vec<char>,
+    masks: Vec<u32>,
+}
+
+impl GlyphTable {
+    fn seven_segment_digits() -> GlyphTable {
+        GlyphTable {
+            num_segments: NUM_SEGMENTS,
+            symbols: "0123456789".chars().collect(),
+            masks: SEGMENT_FOR_NUM.to_vec(),
+        }
+    }
+
+    fn seven_segment_hex() -> GlyphTable {
+        let mut masks = SEGMENT_FOR_NUM.to_vec();
+        masks.extend(SEGMENT_FOR_HEX_LETTER);
+
+        GlyphTable {
+            num_segments: NUM_SEGMENTS,
+            symbols: "0123456789AbCdEF".chars().collect(),
+            masks,
+        }
+    }
+
+    /**
+     * One glyph per line: the symbol, then the segments it lights up, e.g. `7 0,2,5`.
+     * Segments are numbered from 0, and the highest one sets the display size.
+     */
+    fn load(path: &str) -> GlyphTable {
+        let file = File::open(path).unwrap();
+        let reader = BufReader::new(file);
+
+        let mut symbols = vec![];
+        let mut masks = vec![];
+        for line in reader.lines() {
+            let text = line.unwrap();
+            if text.trim().is_empty() {
+                continue;
             }
-            4 => {
-                map.insert(sorted_signal, 4);
+
+            let (symbol, segments) = text
+                .trim()
+                .split_once(char::is_whitespace)
+                .unwrap_or_else(|| panic!("Glyph '{}' should be a symbol and segments", text));
+            let mask = segments.split(',').fold(0u32, |mask, segment| {
+                let segment = segment.trim().parse::<u32>().unwrap();
+                if segment >= u32::BITS {
+                    panic!("Segment {} is out of range", segment);
+                }
+                mask | (1 << segment)
+            });
+
+            symbols.push(symbol.chars().next().unwrap());
+            masks.push(mask);
+        }
+
+        let num_segments = masks
+            .iter()
+            .map(|mask| (u32::BITS - mask.leading_zeros()) as usize)
+            .max()
+            .unwrap_or(0);
+
+        GlyphTable {
+            num_segments,
+            symbols,
+            masks,
+        }
+    }
+
+    /**
+     * -o glyphs=digits (the default), -o glyphs=hex, or -o glyphs=path/to/table
+     */
+    fn from_options() -> GlyphTable {
+        let table = match options::get::<String>("glyphs").as_deref() {
+            None | Some("digits") => GlyphTable::seven_segment_digits(),
+            Some("hex") => GlyphTable::seven_segment_hex(),
+            Some(path) => GlyphTable::load(path),
+        };
+
+        for (i, mask) in table.masks.iter().enumerate() {
+            if table.masks[..i].contains(mask) {
+                panic!(
+                    "Glyph '{}' has the same segments as another",
+                    table.symbols[i]
+                );
+            }
+        }
+
+        table
+    }
+
+    fn find(&self, segments: u32) -> Option<usize> {
+        self.masks.iter().position(|mask| *mask == segments)
+    }
+
+    fn is_seven_segment_digits(&self) -> bool {
+        self.masks == SEGMENT_FOR_NUM
+    }
+}
+
+#[derive(Debug)]
+enum DecodeError {
+    TooManyPatterns(usize, usize),
+    DuplicatePattern(String),
+    UnknownWire(String),
+    // A display pattern that isn't any of the decoded patterns
+    UnmappedDisplay(String),
+    // More than one wiring explains the patterns
+    Ambiguous(String),
+    // No wiring turns every pattern into a different glyph
+    Inconsistent(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::TooManyPatterns(count, glyphs) => {
+                write!(f, "found {} patterns for only {} glyphs", count, glyphs)
             }
-            3 => {
-                map.insert(sorted_signal, 7);
+            DecodeError::DuplicatePattern(pattern) => {
+                write!(f, "pattern '{}' appears more than once", pattern)
             }
-            7 => {
-                map.insert(sorted_signal, 8);
+            DecodeError::UnknownWire(pattern) => {
+                write!(
+                    f,
+                    "pattern '{}' uses a wire the display doesn't have",
+                    pattern
+                )
             }
-            _ => {
-                // pass
+            DecodeError::UnmappedDisplay(pattern) => {
+                write!(
+                    f,
+                    "display pattern '{}' doesn't match any decoded pattern",
+                    pattern
+                )
             }
+            DecodeError::Ambiguous(reason) => write!(f, "ambiguous wiring - {}", reason),
+            DecodeError::Inconsistent(reason) => write!(f, "inconsistent wiring - {}", reason),
         }
     }
+}
 
-    map
+// Wire 'a' is bit 0, 'b' bit 1 and so on. None if the pattern uses a wire the display doesn't have
+fn try_pattern_mask(pattern: &str, num_wires: usize) -> Option<u32> {
+    pattern.chars().try_fold(0, |mask, c| {
+        let wire = (c as u32).wrapping_sub('a' as u32);
+        if wire >= num_wires as u32 {
+            None
+        } else {
+            Some(mask | (1 << wire))
+        }
+    })
 }
 
-fn slot_signals(signals: &Vec<&str>, map: &mut HashMap<String, u32>) {
-    // filter out 1, 4, 7 ,8
-    let unknown_values: Vec<String> = signals
+fn parse_patterns(signals: &[&str], num_wires: usize) -> Result<Vec<u32>, DecodeError> {
+    signals
         .iter()
-        .filter(|s| match s.len() {
-            2 | 3 | 4 | 7 => false,
-            _ => true,
+        .map(|signal| {
+            try_pattern_mask(signal, num_wires)
+                .ok_or_else(|| DecodeError::UnknownWire(signal.to_string()))
         })
-        .map(|x| x.to_string())
-        .collect();
+        .collect()
+}
 
-    let mut char_to_segment_map: HashMap<char, u32> = HashMap::new();
+fn wire_name(wire: usize) -> char {
+    (b'a' + wire as u8) as char
+}
 
-    let mut known_values: Vec<(&String, &u32)> = map.iter().collect();
+// The pattern's wires in alphabetical order, for messages
+fn pattern_name(mask: u32) -> String {
+    (0..u32::BITS as usize)
+        .filter(|wire| mask & (1 << wire) != 0)
+        .map(wire_name)
+        .collect()
+}
 
-    let mut unknown_map: HashMap<String, u32> = HashMap::new();
+fn translate(mask: u32, wire_to_segment: &[usize]) -> u32 {
+    (0..wire_to_segment.len())
+        .filter(|wire| mask & (1 << wire) != 0)
+        .fold(0, |segments, wire| segments | (1 << wire_to_segment[wire]))
+}
 
-    let result = slot_values(
-        &mut char_to_segment_map,
-        &mut known_values,
-        &unknown_values,
-        &mut unknown_map,
-    );
+/**
+ * Works out which wire drives which segment from the ten unique digit patterns.
+ *
+ * Across the ten digits, each segment lights up a fixed number of times:
+ * segment 1 six times, 4 four times and 5 nine times, so those wires are known straight away.
+ * Segments 0 and 2 both appear eight times, but only 2 is part of the 1.
+ * Segments 3 and 6 both appear seven times, but only 3 is part of the 4.
+ */
+fn decode_wiring(patterns: &[u32]) -> Result<Vec<usize>, DecodeError> {
+    let mut frequencies = [0; NUM_SEGMENTS];
+    for mask in patterns {
+        for (wire, frequency) in frequencies.iter_mut().enumerate() {
+            if mask & (1 << wire) != 0 {
+                *frequency += 1;
+            }
+        }
+    }
+
+    let wires_seen = |count: u32| -> Vec<usize> {
+        (0..NUM_SEGMENTS)
+            .filter(|wire| frequencies[*wire] == count)
+            .collect()
+    };
+
+    let one = *patterns
+        .iter()
+        .find(|mask| mask.count_ones() == 2)
+        .ok_or_else(|| DecodeError::Ambiguous("no pattern for 1".to_string()))?;
+    let four = *patterns
+        .iter()
+        .find(|mask| mask.count_ones() == 4)
+        .ok_or_else(|| DecodeError::Ambiguous("no pattern for 4".to_string()))?;
+
+    let mut wire_to_segment = vec![usize::MAX; NUM_SEGMENTS];
+    for (count, segment) in [(6, 1), (4, 4), (9, 5)] {
+        match wires_seen(count)[..] {
+            [wire] => wire_to_segment[wire] = segment,
+            _ => {
+                return Err(DecodeError::Ambiguous(format!(
+                    "segment {} needs exactly one wire seen {} times",
+                    segment, count
+                )))
+            }
+        }
+    }
+
+    // (times seen, pattern telling them apart, segment inside it, segment outside it)
+    for (count, known, inside, outside) in [(8, one, 2, 0), (7, four, 3, 6)] {
+        match wires_seen(count)[..] {
+            [first, second] => {
+                let first_inside = known & (1 << first) != 0;
+                let second_inside = known & (1 << second) != 0;
+                if first_inside == second_inside {
+                    return Err(DecodeError::Ambiguous(format!(
+                        "can't tell segments {} and {} apart",
+                        inside, outside
+                    )));
+                }
+
+                let (in_wire, out_wire) = if first_inside {
+                    (first, second)
+                } else {
+                    (second, first)
+                };
+                wire_to_segment[in_wire] = inside;
+                wire_to_segment[out_wire] = outside;
+            }
+            _ => {
+                return Err(DecodeError::Ambiguous(format!(
+                    "segments {} and {} need exactly two wires seen {} times",
+                    inside, outside, count
+                )))
+            }
+        }
+    }
 
-    assert_eq!(result, true);
+    Ok(wire_to_segment)
+}
 
-    // Annoying borrowing workaround
-    for (key, value) in unknown_map {
-        map.insert(key, value);
+/**
+ * Checks every pattern turns into a different glyph under the wiring
+ */
+fn check_wiring(
+    patterns: &[u32],
+    wire_to_segment: &[usize],
+    table: &GlyphTable,
+) -> Result<(), DecodeError> {
+    let mut seen_glyphs = vec![false; table.masks.len()];
+    for mask in patterns {
+        match table.find(translate(*mask, wire_to_segment)) {
+            Some(glyph) if !seen_glyphs[glyph] => seen_glyphs[glyph] = true,
+            Some(glyph) => {
+                return Err(DecodeError::Inconsistent(format!(
+                    "'{}' shows up twice",
+                    table.symbols[glyph]
+                )))
+            }
+            None => {
+                return Err(DecodeError::Inconsistent(format!(
+                    "pattern '{}' isn't a glyph",
+                    pattern_name(*mask)
+                )))
+            }
+        }
     }
+
+    Ok(())
 }
 
-// The only point of this is to see if we can fill segments given a permutation
-fn try_fill_clock_with_chars(
-    char_to_segment_map: &mut HashMap<char, u32>,
-    pairs: &Vec<(&char, &u32)>,
-) -> bool {
-    let mut entries = vec![];
-    for (c, i) in pairs {
-        match char_to_segment_map.entry(**c) {
-            Entry::Vacant(o) => {
-                o.insert(**i);
-                entries.push(c);
+/*
+    General wiring solver, for any glyph table.
+
+    Every wire starts out able to drive any segment, kept as a bitmask of candidates.
+    Each pattern can only be one of the glyphs with the same segment count, so its wires
+    must drive segments of those glyphs, and the wires outside it must not. Wires with a
+    single candidate take that segment away from the rest. This repeats until nothing changes.
+
+    What's left is a bipartite matching between wires and segments. If there's no perfect
+    matching the patterns are inconsistent. Otherwise the wire with the fewest candidates is
+    tried against each of them in turn, stopping once two full wirings are found.
+*/
+struct WiringSolver<'a> {
+    patterns: &'a [u32],
+    table: &'a GlyphTable,
+    num_wires: usize,
+    full_mask: u32,
+}
+
+impl WiringSolver<'_> {
+    fn new<'a>(patterns: &'a [u32], table: &'a GlyphTable) -> WiringSolver<'a> {
+        let full_mask = if table.num_segments == u32::BITS as usize {
+            u32::MAX
+        } else {
+            (1 << table.num_segments) - 1
+        };
+
+        WiringSolver {
+            patterns,
+            table,
+            num_wires: table.num_segments,
+            full_mask,
+        }
+    }
+
+    /**
+     * Glyphs a pattern could still be: the same segment count, every wire in the pattern
+     * able to drive one of the glyph's segments, and every wire outside able to drive one
+     * of the others
+     */
+    fn possible_glyphs(&self, pattern: u32, candidates: &[u32]) -> Vec<usize> {
+        (0..self.table.masks.len())
+            .filter(|glyph| {
+                let glyph_mask = self.table.masks[*glyph];
+                glyph_mask.count_ones() == pattern.count_ones()
+                    && (0..self.num_wires).all(|wire| {
+                        if pattern & (1 << wire) != 0 {
+                            candidates[wire] & glyph_mask != 0
+                        } else {
+                            candidates[wire] & !glyph_mask & self.full_mask != 0
+                        }
+                    })
+            })
+            .collect()
+    }
+
+    fn propagate(&self, candidates: &mut [u32]) -> bool {
+        loop {
+            let before = candidates.to_vec();
+
+            for pattern in self.patterns {
+                let mut inside = 0;
+                let mut outside = 0;
+                for glyph in self.possible_glyphs(*pattern, candidates) {
+                    inside |= self.table.masks[glyph];
+                    outside |= !self.table.masks[glyph] & self.full_mask;
+                }
+
+                for (wire, candidate) in candidates.iter_mut().enumerate() {
+                    if pattern & (1 << wire) != 0 {
+                        *candidate &= inside;
+                    } else {
+                        *candidate &= outside;
+                    }
+                }
             }
-            Entry::Occupied(o) => {
-                if *o.get() != **i {
-                    // This will not work. Rewind and abort
-                    for entry in entries {
-                        char_to_segment_map.remove(entry);
+
+            for wire in 0..self.num_wires {
+                if candidates[wire].count_ones() == 1 {
+                    for other in 0..self.num_wires {
+                        if other != wire {
+                            candidates[other] &= !candidates[wire];
+                        }
                     }
+                }
+            }
+
+            if candidates.contains(&0) {
+                return false;
+            }
+
+            if candidates == before.as_slice() {
+                return true;
+            }
+        }
+    }
+
+    // Kuhn's augmenting path algorithm, only used to tell whether a perfect matching exists
+    fn has_perfect_matching(&self, candidates: &[u32]) -> bool {
+        fn augment(
+            wire: usize,
+            candidates: &[u32],
+            segment_owner: &mut [Option<usize>],
+            visited: &mut [bool],
+        ) -> bool {
+            for segment in 0..segment_owner.len() {
+                if candidates[wire] & (1 << segment) == 0 || visited[segment] {
+                    continue;
+                }
+
+                visited[segment] = true;
+                let free = match segment_owner[segment] {
+                    None => true,
+                    Some(owner) => augment(owner, candidates, segment_owner, visited),
+                };
 
-                    return false;
+                if free {
+                    segment_owner[segment] = Some(wire);
+                    return true;
                 }
             }
+
+            false
         }
+
+        let mut segment_owner = vec![None; self.table.num_segments];
+        (0..self.num_wires).all(|wire| {
+            let mut visited = vec![false; self.table.num_segments];
+            augment(wire, candidates, &mut segment_owner, &mut visited)
+        })
     }
 
-    true
+    fn search(&self, mut candidates: Vec<u32>, solutions: &mut Vec<Vec<usize>>) {
+        if solutions.len() > 1
+            || !self.propagate(&mut candidates)
+            || !self.has_perfect_matching(&candidates)
+        {
+            return;
+        }
+
+        let undecided = (0..self.num_wires)
+            .filter(|wire| candidates[*wire].count_ones() > 1)
+            .min_by_key(|wire| candidates[*wire].count_ones());
+
+        match undecided {
+            None => {
+                let wiring: Vec<usize> = candidates
+                    .iter()
+                    .map(|candidate| candidate.trailing_zeros() as usize)
+                    .collect();
+                if check_wiring(self.patterns, &wiring, self.table).is_ok() {
+                    solutions.push(wiring);
+                }
+            }
+            Some(wire) => {
+                for segment in 0..self.table.num_segments {
+                    if candidates[wire] & (1 << segment) != 0 {
+                        let mut next = candidates.clone();
+                        next[wire] = 1 << segment;
+                        self.search(next, solutions);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn match_wiring(patterns: &[u32], table: &GlyphTable) -> Result<Vec<usize>, DecodeError> {
+    let solver = WiringSolver::new(patterns, table);
+
+    let mut solutions = vec![];
+    solver.search(vec![solver.full_mask; table.num_segments], &mut solutions);
+
+    match solutions.len() {
+        0 => Err(DecodeError::Inconsistent(
+            "no wiring matches every pattern to a glyph".to_string(),
+        )),
+        1 => Ok(solutions.remove(0)),
+        _ => {
+            let differing: Vec<char> = (0..table.num_segments)
+                .filter(|wire| solutions[0][*wire] != solutions[1][*wire])
+                .map(wire_name)
+                .collect();
+            Err(DecodeError::Ambiguous(format!(
+                "wires {:?} can be swapped",
+                differing
+            )))
+        }
+    }
 }
 
-fn remove_segments_from_map(char_to_segment_map: &mut HashMap<char, u32>, chars: &Vec<char>) {
-    for c in chars {
-        char_to_segment_map.remove(c);
+/**
+ * Decodes the patterns and adds every one of them to the map.
+ * Returns the wire to segment mapping it found.
+ */
+fn slot_signals(
+    patterns: &[u32],
+    table: &GlyphTable,
+    map: &mut PatternMap,
+) -> Result<Vec<usize>, DecodeError> {
+    for (i, mask) in patterns.iter().enumerate() {
+        if patterns[..i].contains(mask) {
+            return Err(DecodeError::DuplicatePattern(pattern_name(*mask)));
+        }
+    }
+
+    if patterns.len() > table.masks.len() {
+        return Err(DecodeError::TooManyPatterns(
+            patterns.len(),
+            table.masks.len(),
+        ));
+    }
+
+    // The frequency trick only works with all ten digits. Anything else goes to the matcher,
+    // which also explains what's wrong when the trick fails
+    let decoded = if table.is_seven_segment_digits() && patterns.len() == 10 {
+        decode_wiring(patterns)
+            .ok()
+            .filter(|wiring| check_wiring(patterns, wiring, table).is_ok())
+    } else {
+        None
+    };
+
+    let wire_to_segment = match decoded {
+        Some(wiring) => wiring,
+        None => match_wiring(patterns, table)?,
+    };
+
+    for mask in patterns {
+        let glyph = table.find(translate(*mask, &wire_to_segment)).unwrap();
+        map.insert(*mask, glyph as u32);
     }
+
+    Ok(wire_to_segment)
+}
+
+/**
+ * Everything that could be worked out about a line, even when it doesn't decode
+ */
+#[derive(Debug)]
+struct LineDiagnosis {
+    duplicates: Vec<String>,
+    // Patterns using unknown wires, or a segment count no glyph has
+    invalid: Vec<String>,
+    missing: usize,
+    extra: usize,
+    // Patterns that make the line inconsistent, since the rest decode without them
+    suspects: Vec<String>,
+    // Bitmask of the segments each wire could still drive
+    candidates: Vec<u32>,
+    // Glyphs no pattern can be pinned to
+    undecidable: Vec<char>,
+    error: Option<DecodeError>,
 }
 
-fn compare_vecs(a: &Vec<u32>, b: &Vec<u32>) -> bool {
-    if a.len() != b.len() {
-        return false;
+fn diagnose_line(signals: &[&str], table: &GlyphTable) -> LineDiagnosis {
+    let mut duplicates = vec![];
+    let mut invalid = vec![];
+    let mut valid: Vec<(&str, u32)> = vec![];
+    for signal in signals {
+        match try_pattern_mask(signal, table.num_segments) {
+            Some(mask) if valid.iter().any(|(_, m)| *m == mask) => {
+                duplicates.push(signal.to_string())
+            }
+            Some(mask)
+                if table
+                    .masks
+                    .iter()
+                    .any(|g| g.count_ones() == mask.count_ones()) =>
+            {
+                valid.push((signal, mask))
+            }
+            _ => invalid.push(signal.to_string()),
+        }
     }
 
-    for i in 0..a.len() {
-        if a[i] != b[i] {
-            return false;
+    let masks: Vec<u32> = valid.iter().map(|(_, mask)| *mask).collect();
+    let missing = table.masks.len().saturating_sub(masks.len());
+    let extra = masks.len().saturating_sub(table.masks.len());
+
+    let decoded = parse_patterns(signals, table.num_segments)
+        .and_then(|patterns| slot_signals(&patterns, table, &mut PatternMap::default()));
+
+    // Narrow the wiring as far as it goes. If that contradicts itself, look for the
+    // patterns that can be dropped to make the rest work, and narrow without the first
+    let consistent = |masks: &[u32]| -> Option<Vec<u32>> {
+        let solver = WiringSolver::new(masks, table);
+        let mut candidates = vec![solver.full_mask; table.num_segments];
+        if solver.propagate(&mut candidates) && solver.has_perfect_matching(&candidates) {
+            Some(candidates)
+        } else {
+            None
+        }
+    };
+
+    let mut suspects = vec![];
+    let mut kept = masks.clone();
+    let mut candidates = consistent(&masks);
+    if candidates.is_none() {
+        for (i, (signal, _)) in valid.iter().enumerate() {
+            let mut without = masks.clone();
+            without.remove(i);
+            if let Some(found) = consistent(&without) {
+                if suspects.is_empty() {
+                    candidates = Some(found);
+                    kept = without;
+                }
+                suspects.push(signal.to_string());
+            }
         }
     }
 
-    true
+    let solver = WiringSolver::new(&kept, table);
+    let candidates = match &decoded {
+        Ok(wiring) => wiring.iter().map(|segment| 1 << segment).collect(),
+        Err(_) => candidates.unwrap_or_else(|| vec![solver.full_mask; table.num_segments]),
+    };
+
+    let mut decided = vec![false; table.masks.len()];
+    for mask in &kept {
+        if let [glyph] = solver.possible_glyphs(*mask, &candidates)[..] {
+            decided[glyph] = true;
+        }
+    }
+
+    let undecidable = (0..table.masks.len())
+        .filter(|glyph| !decided[*glyph])
+        .map(|glyph| table.symbols[glyph])
+        .collect();
+
+    LineDiagnosis {
+        duplicates,
+        invalid,
+        missing,
+        extra,
+        suspects,
+        candidates,
+        undecidable,
+        error: decoded.err(),
+    }
 }
 
-fn compare_all_vecs(a: &Vec<u32>, b: &[Vec<u32>; 10]) -> Option<u32> {
-    for (e, other_seg) in b.iter().enumerate() {
-        if compare_vecs(a, other_seg) {
-            return Some(e as u32);
+/**
+ * The wiring drawn on a seven segment display, with each segment showing the wire
+ * driving it, or '?' while it's still unknown. Like the puzzle's own example:
+ *
+ *    dddd
+ *   e    a
+ *   e    a
+ *    ffff
+ *   g    b
+ *   g    b
+ *    cccc
+ */
+fn draw_wiring(candidates: &[u32]) -> Vec<String> {
+    let mut segment_wire = ['?'; NUM_SEGMENTS];
+    for (wire, candidate) in candidates.iter().enumerate() {
+        if candidate.count_ones() == 1 {
+            segment_wire[candidate.trailing_zeros() as usize] = wire_name(wire);
         }
     }
 
-    None
+    let horizontal = |segment: usize| format!(" {} ", segment_wire[segment].to_string().repeat(4));
+    let vertical =
+        |left: usize, right: usize| format!("{}    {}", segment_wire[left], segment_wire[right]);
+
+    vec![
+        horizontal(0),
+        vertical(1, 2),
+        vertical(1, 2),
+        horizontal(3),
+        vertical(4, 5),
+        vertical(4, 5),
+        horizontal(6),
+    ]
 }
 
-fn slot_values(
-    char_to_segment_map: &mut HashMap<char, u32>,
-    known_values: &mut Vec<(&String, &u32)>,
-    unknown_values: &Vec<String>,
-    char_num_map: &mut HashMap<String, u32>,
-) -> bool {
-    /*
-      If you treat every edge like a number, then each number has this mapping
-       0
-      1 2
-       3
-      4 5
-       6
+fn print_diagnosis(line_num: usize, diagnosis: &LineDiagnosis, table: &GlyphTable) {
+    match &diagnosis.error {
+        None => println!("Line {} - ok", line_num),
+        Some(err) => println!("Line {} - {}", line_num, err),
+    }
 
-       0 - 0, 1, 2, 4, 5, 6
-       1 - 2, 5
-       2 - 0, 2, 3, 4, 6
-       3 - 0, 2, 3, 5, 6
-       4 - 1, 2, 3, 5
-       5 - 0, 1, 3, 5, 6
-       6 - 0, 1, 3, 4, 5, 6
-       7 - 0, 2, 5
-       8 - 0, 1, 2, 3, 4, 5, 6
-       9 - 0, 1, 2, 3, 5, 6
-    */
-    lazy_static! {
-        static ref SEGMENT_FOR_NUM: [Vec<u32>; 10] = [
-            vec![0, 1, 2, 4, 5, 6],
-            vec![2, 5],
-            vec![0, 2, 3, 4, 6],
-            vec![0, 2, 3, 5, 6],
-            vec![1, 2, 3, 5],
-            vec![0, 1, 3, 5, 6],
-            vec![0, 1, 3, 4, 5, 6],
-            vec![0, 2, 5],
-            vec![0, 1, 2, 3, 4, 5, 6],
-            vec![0, 1, 2, 3, 5, 6],
-        ];
+    for pattern in &diagnosis.duplicates {
+        println!("  duplicate pattern '{}'", pattern);
     }
 
-    if known_values.len() == 0 {
-        // Have to try to fit the unknown values, to really make sure this permutation is correct
-        let mut pairs = vec![];
-        for unknown in unknown_values {
-            let mut segs = vec![];
-            for c in unknown.chars() {
-                segs.push(char_to_segment_map.get(&c).unwrap().clone());
-            }
+    for pattern in &diagnosis.invalid {
+        println!("  pattern '{}' can't be any glyph", pattern);
+    }
 
-            segs.sort();
-            let number = compare_all_vecs(&segs, &SEGMENT_FOR_NUM);
-            if number.is_none() {
-                return false;
-            } else {
-                pairs.push((unknown, number.unwrap().clone()));
-            }
-        }
+    if diagnosis.missing > 0 {
+        println!("  {} patterns missing", diagnosis.missing);
+    }
 
-        for pair in pairs {
-            char_num_map.insert(sort_string(pair.0), pair.1);
+    if diagnosis.extra > 0 {
+        println!("  {} patterns too many", diagnosis.extra);
+    }
+
+    for pattern in &diagnosis.suspects {
+        println!("  everything else decodes without '{}'", pattern);
+    }
+
+    if table.num_segments == NUM_SEGMENTS {
+        for row in draw_wiring(&diagnosis.candidates) {
+            println!("    {}", row);
+        }
+    } else {
+        for (wire, candidate) in diagnosis.candidates.iter().enumerate() {
+            let segments: Vec<String> = (0..table.num_segments)
+                .filter(|segment| candidate & (1 << segment) != 0)
+                .map(|segment| segment.to_string())
+                .collect();
+            println!("    {} - {}", wire_name(wire), segments.join("/"));
         }
+    }
 
-        return true;
+    if !diagnosis.undecidable.is_empty() {
+        let symbols: Vec<String> = diagnosis
+            .undecidable
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        println!("  undecidable - {}", symbols.join(", "));
     }
+}
+
+fn run_diagnostics() {
+    let file = File::open("inputs/day8/input.txt").unwrap();
+    let reader = BufReader::new(file);
 
-    let (current_str, current_num) = known_values.pop().unwrap();
+    let table = GlyphTable::from_options();
 
-    let current_chars = current_str.chars().collect::<Vec<char>>();
-    let positions = &SEGMENT_FOR_NUM[*current_num as usize];
+    let mut lines = 0;
+    let mut broken = 0;
+    for (line_num, line) in reader.lines().enumerate() {
+        let input = line.unwrap();
+        let signals: Vec<&str> = input
+            .split('|')
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .collect();
 
-    /*
-        We are going to try to recursively map segments to a number, for all possible permutations
-        for the known values (1, 4, 7, 8).
-        Since there's multiple ways this can work, we then need to try to fit the unknown values.
+        let diagnosis = diagnose_line(&signals, &table);
+        print_diagnosis(line_num + 1, &diagnosis, &table);
 
-        At the end, there will be one possible mapping
-    */
-    for perm in positions.iter().permutations(positions.len()) {
-        if try_fill_clock_with_chars(char_to_segment_map, &zip(&current_chars, perm).collect()) {
-            if slot_values(
-                char_to_segment_map,
-                known_values,
-                unknown_values,
-                char_num_map,
-            ) {
-                return true;
-            } else {
-                remove_segments_from_map(char_to_segment_map, &current_chars);
-            }
+        lines += 1;
+        if diagnosis.error.is_some() {
+            broken += 1;
         }
     }
 
-    /*
-        d - 0
-        e - 1
-        a - 2
-        f - 3
-        g - 4
-        b - 5
-        c - 6
+    println!("{} of {} lines could not be decoded", broken, lines);
+}
+
+/**
+ * Glyph shown at each display position, or None for a pattern that wasn't mapped.
+ * Part 1 only maps the patterns recognisable by length, part 2 decodes the whole wiring
+ * and fails if any display pattern is left unmapped.
+ */
+fn decode_line(
+    input: &str,
+    table: &GlyphTable,
+    variant: &QVariant,
+) -> Result<Vec<Option<u32>>, DecodeError> {
+    let (input_signals, display_signals) = input.split_once('|').unwrap_or((input, ""));
+    let signals: Vec<&str> = input_signals.split_whitespace().collect();
+
+    let map = match variant {
+        QVariant::Part1 => {
+            let patterns: Vec<u32> = signals
+                .iter()
+                .filter_map(|signal| try_pattern_mask(signal, table.num_segments))
+                .collect();
+            map_signals(&patterns, table)
+        }
+        QVariant::Part2 => {
+            let patterns = parse_patterns(&signals, table.num_segments)?;
+            let mut map = PatternMap::default();
+            slot_signals(&patterns, table, &mut map)?;
+            map
+        }
+    };
 
-        ddd
-       f   b
-       f   b
-        eee
-       g   a
-       g   a
-        ccc
-    */
-    known_values.push((current_str, current_num));
-    false
+    let mut glyphs = vec![];
+    for signal in display_signals.split_whitespace() {
+        let glyph =
+            try_pattern_mask(signal, table.num_segments).and_then(|pattern| map.get(pattern));
+        if glyph.is_none() && matches!(variant, QVariant::Part2) {
+            return Err(DecodeError::UnmappedDisplay(signal.to_string()));
+        }
+        glyphs.push(glyph);
+    }
+
+    Ok(glyphs)
+}
+
+/**
+ * Displays are read as numbers in base <number of glyphs>.
+ * None if any position wasn't mapped to a glyph.
+ */
+fn display_value(glyphs: &[Option<u32>], table: &GlyphTable) -> Option<u64> {
+    let base = table.masks.len() as u64;
+    glyphs
+        .iter()
+        .try_fold(0, |value, glyph| Some(value * base + (*glyph)? as u64))
 }
 
 fn run_problem(variant: QVariant) {
     let file = File::open("inputs/day8/input.txt").unwrap();
     let reader = BufReader::new(file);
 
-    let mut count = 0;
-    for line in reader.lines() {
+    let table = GlyphTable::from_options();
+    let show_displays = options::get::<String>("glyphs").is_some();
+
+    let mut count: u64 = 0;
+    let mut skipped = 0;
+    for (line_num, line) in reader.lines().enumerate() {
         let input = line.unwrap();
-        let tokens: Vec<&str> = input.split('|').collect();
+        // Lines that don't decode are diagnosed and left out of the answer
+        let glyphs = match decode_line(&input, &table, &variant) {
+            Ok(glyphs) => glyphs,
+            Err(err) => {
+                let signals: Vec<&str> = input
+                    .split('|')
+                    .next()
+                    .unwrap_or("")
+                    .split_whitespace()
+                    .collect();
 
-        let input_signals: Vec<&str> = tokens[0].split_whitespace().collect();
-        let display_signals: Vec<&str> = tokens[1].split_whitespace().collect();
+                // The patterns can be fine while the display isn't, so keep the error it hit
+                let mut diagnosis = diagnose_line(&signals, &table);
+                if diagnosis.error.is_none() {
+                    diagnosis.error = Some(err);
+                }
+                print_diagnosis(line_num + 1, &diagnosis, &table);
+                skipped += 1;
+                continue;
+            }
+        };
 
-        let mut map = map_signals(&input_signals);
+        match variant {
+            QVariant::Part1 => {
+                count += glyphs.iter().filter(|glyph| glyph.is_some()).count() as u64;
+            }
+            QVariant::Part2 => {
+                // decode_line fails for part 2 if any display pattern is unmapped
+                count += display_value(&glyphs, &table).unwrap();
+            }
+        }
 
-        if matches!(variant, QVariant::Part2) {
-            slot_signals(&input_signals, &mut map);
+        if show_displays && matches!(variant, QVariant::Part2) {
+            let display: String = glyphs
+                .iter()
+                .map(|glyph| glyph.map_or('?', |glyph| table.symbols[glyph as usize]))
+                .collect();
+            println!("{}", display);
         }
+    }
 
-        let base: i32 = 10;
-        for (e, signal) in display_signals.iter().enumerate() {
-            let size = display_signals.len();
-            let digit = (size - e - 1) as u32;
-            let key = &sort_string(signal);
-            if map.contains_key(&sort_string(signal)) {
-                match variant {
-                    QVariant::Part1 => {
-                        count += 1;
-                    }
-                    QVariant::Part2 => {
-                        count += map.get(key).unwrap() * (base.pow(digit) as u32);
-                    }
-                }
+    if skipped > 0 {
+        println!("Skipped {} lines that could not be decoded", skipped);
+    }
+
+    println!("Answer - {}", count);
+}
+
+const BENCHMARK_SEED: u64 = 2021;
+const BENCHMARK_LINES: usize = 1_000_000;
+const BENCHMARK_DISPLAY_SIZE: usize = 4;
+
+/**
+ * Lines in the puzzle format, each with a random wiring, the ten digit patterns
+ * in random order and a random four digit display. Wires inside a pattern are shuffled too.
+ */
+fn generate_notes(seed: u64, num_lines: usize) -> Vec<String> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut segment_to_wire: Vec<usize> = (0..NUM_SEGMENTS).collect();
+
+    let pattern_for = |rng: &mut StdRng, segment_to_wire: &[usize], digit: usize| {
+        let mut wires: Vec<char> = (0..NUM_SEGMENTS)
+            .filter(|segment| SEGMENT_FOR_NUM[digit] & (1 << segment) != 0)
+            .map(|segment| wire_name(segment_to_wire[segment]))
+            .collect();
+        wires.shuffle(rng);
+        wires.into_iter().collect::<String>()
+    };
+
+    (0..num_lines)
+        .map(|_| {
+            segment_to_wire.shuffle(&mut rng);
+            let mut digits: Vec<usize> = (0..SEGMENT_FOR_NUM.len()).collect();
+            digits.shuffle(&mut rng);
+
+            let signals: Vec<String> = digits
+                .iter()
+                .map(|digit| pattern_for(&mut rng, &segment_to_wire, *digit))
+                .collect();
+            let display: Vec<String> = (0..BENCHMARK_DISPLAY_SIZE)
+                .map(|_| {
+                    let digit = rng.gen_range(0..SEGMENT_FOR_NUM.len());
+                    pattern_for(&mut rng, &segment_to_wire, digit)
+                })
+                .collect();
+
+            format!("{} | {}", signals.join(" "), display.join(" "))
+        })
+        .collect()
+}
+
+/*
+    The pre-bitmask representation, kept to benchmark against. Patterns are sorted Strings,
+    wires are chars looked up through HashMaps, and a decoded pattern is a sorted Vec of
+    segments compared against each digit's list. It runs the same steps as the bitmask path
+    on a ten digit line: duplicate and size checks, the frequency decode, then checking every
+    pattern turns into a different digit.
+*/
+fn sort_string(s: &str) -> String {
+    let mut char_arr = s.chars().collect::<Vec<char>>();
+    char_arr.sort_by(|a, b| b.cmp(a));
+    String::from_iter(char_arr)
+}
+
+fn compare_vecs(a: &[u32], b: &[u32]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(x, y)| x == y)
+}
+
+fn compare_all_vecs(a: &[u32], segments_for_num: &[Vec<u32>]) -> Option<u32> {
+    segments_for_num
+        .iter()
+        .position(|other_seg| compare_vecs(a, other_seg))
+        .map(|num| num as u32)
+}
+
+fn legacy_decode_wiring(signals: &[String]) -> Option<HashMap<char, u32>> {
+    let mut frequencies: HashMap<char, u32> = HashMap::new();
+    for signal in signals {
+        for c in signal.chars() {
+            *frequencies.entry(c).or_insert(0) += 1;
+        }
+    }
+
+    let wires_seen = |count: u32| -> Vec<char> {
+        let mut wires: Vec<char> = frequencies
+            .iter()
+            .filter(|(_, seen)| **seen == count)
+            .map(|(c, _)| *c)
+            .collect();
+        wires.sort();
+        wires
+    };
+
+    let one = signals.iter().find(|signal| signal.len() == 2)?;
+    let four = signals.iter().find(|signal| signal.len() == 4)?;
+
+    let mut char_to_segment_map: HashMap<char, u32> = HashMap::new();
+    for (count, segment) in [(6, 1), (4, 4), (9, 5)] {
+        match wires_seen(count)[..] {
+            [c] => char_to_segment_map.insert(c, segment),
+            _ => return None,
+        };
+    }
+
+    for (count, known, inside, outside) in [(8, one, 2, 0), (7, four, 3, 6)] {
+        match wires_seen(count)[..] {
+            [first, second] if known.contains(first) != known.contains(second) => {
+                let (in_char, out_char) = if known.contains(first) {
+                    (first, second)
+                } else {
+                    (second, first)
+                };
+                char_to_segment_map.insert(in_char, inside);
+                char_to_segment_map.insert(out_char, outside);
             }
+            _ => return None,
         }
     }
 
-    println!("Answer - {}", count);
+    Some(char_to_segment_map)
+}
+
+/**
+ * Display value of a generated line, or None where the bitmask path would have
+ * needed the general matcher
+ */
+fn legacy_display_value(input: &str, segments_for_num: &[Vec<u32>]) -> Option<u64> {
+    let tokens: Vec<&str> = input.split('|').collect();
+    let input_signals: Vec<String> = tokens[0].split_whitespace().map(sort_string).collect();
+    let display_signals: Vec<String> = tokens[1].split_whitespace().map(sort_string).collect();
+
+    for (i, signal) in input_signals.iter().enumerate() {
+        if input_signals[..i].contains(signal) {
+            return None;
+        }
+    }
+
+    if input_signals.len() != segments_for_num.len() {
+        return None;
+    }
+
+    let char_to_segment_map = legacy_decode_wiring(&input_signals)?;
+
+    let mut map: HashMap<String, u32> = HashMap::new();
+    for signal in &input_signals {
+        let mut segs = signal
+            .chars()
+            .map(|c| char_to_segment_map.get(&c).copied())
+            .collect::<Option<Vec<u32>>>()?;
+        segs.sort();
+
+        let number = compare_all_vecs(&segs, segments_for_num)?;
+        if map.values().any(|seen| *seen == number) {
+            return None;
+        }
+        map.insert(signal.clone(), number);
+    }
+
+    display_signals.iter().try_fold(0, |value, signal| {
+        map.get(signal)
+            .map(|number| value * segments_for_num.len() as u64 + *number as u64)
+    })
+}
+
+/**
+ * Compares the sorted String decoding against the bitmask one on generated notes
+ */
+fn run_benchmark() {
+    let table = GlyphTable::seven_segment_digits();
+    let segments_for_num: Vec<Vec<u32>> = SEGMENT_FOR_NUM
+        .iter()
+        .map(|mask| {
+            (0..NUM_SEGMENTS as u32)
+                .filter(|s| mask & (1 << s) != 0)
+                .collect()
+        })
+        .collect();
+
+    let notes = generate_notes(BENCHMARK_SEED, BENCHMARK_LINES);
+    println!("Generated {} lines (seed {})", notes.len(), BENCHMARK_SEED);
+
+    let now = Instant::now();
+    let legacy_total: u64 = notes
+        .iter()
+        .map(|line| legacy_display_value(line, &segments_for_num).unwrap())
+        .sum();
+    let legacy_elapsed = now.elapsed();
+    println!("strings  - {} in {:.2?}", legacy_total, legacy_elapsed);
+
+    let now = Instant::now();
+    let total: u64 = notes
+        .iter()
+        .map(|line| {
+            display_value(
+                &decode_line(line, &table, &QVariant::Part2).unwrap(),
+                &table,
+            )
+            .unwrap()
+        })
+        .sum();
+    let elapsed = now.elapsed();
+    println!("bitmasks - {} in {:.2?}", total, elapsed);
+
+    assert_eq!(legacy_total, total);
+    println!(
+        "Speedup - {:.2}x",
+        legacy_elapsed.as_secs_f64() / elapsed.as_secs_f64()
+    );
 }
 
 pub fn part1() {
@@ -292,3 +1173,11 @@ pub fn part1() {
 pub fn part2() {
     run_problem(QVariant::Part2);
 }
+
+pub fn part3() {
+    run_diagnostics();
+}
+
+pub fn part4() {
+    run_benchmark();
+}
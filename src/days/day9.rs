@@ -1,10 +1,15 @@
-use std::collections::HashSet;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
+use crate::options;
+
 enum QVariant {
     Part1,
     Part2,
+    Basins,
+    Flood,
 }
 
 #[derive(Debug, Hash, Copy, Clone)]
@@ -20,123 +25,534 @@ impl Eq for Point {}
 
 const IMPASSABLE_NUM: u32 = 9;
 
-// Simple DFS. We have the advantage of knowing that basins will NEVER touch each other
-fn determine_basin(point: Point, height_map: &Vec<Vec<u32>>, visited: &mut HashSet<Point>) -> u32 {
-    if visited.contains(&point) {
-        return 0;
+/**
+ * Heights read from the input, where any cell at or above the impassable height is a wall.
+ * -o impassable=H changes it from the default 9.
+ */
+struct HeightMap {
+    heights: Vec<Vec<u32>>,
+    impassable: u32,
+}
+
+impl HeightMap {
+    fn parse<R: BufRead>(reader: R) -> HeightMap {
+        let mut heights: Vec<Vec<u32>> = vec![];
+        for line in reader.lines() {
+            let input = line.unwrap();
+            let mut row = vec![];
+            for i in input.chars().map(|c| c.to_digit(10).unwrap()) {
+                row.push(i);
+            }
+
+            heights.push(row);
+        }
+
+        HeightMap {
+            heights,
+            impassable: options::get_or("impassable", IMPASSABLE_NUM),
+        }
     }
 
-    visited.insert(point);
+    fn rows(&self) -> usize {
+        self.heights.len()
+    }
 
-    let current_val = height_map[point.0][point.1];
-    if current_val == IMPASSABLE_NUM {
-        return 0;
+    fn cols(&self) -> usize {
+        self.heights[0].len()
     }
 
-    let mut size = 0;
+    fn height(&self, point: Point) -> u32 {
+        self.heights[point.0][point.1]
+    }
 
-    let row_len = height_map.len();
-    let col_len = height_map[0].len();
+    fn is_wall(&self, point: Point) -> bool {
+        self.height(point) >= self.impassable
+    }
 
-    if point.0 > 0 && height_map[point.0 - 1][point.1] >= current_val {
-        size += determine_basin(Point(point.0 - 1, point.1), &height_map, visited);
+    fn points(&self) -> impl Iterator<Item = Point> + '_ {
+        (0..self.rows()).flat_map(move |i| (0..self.cols()).map(move |j| Point(i, j)))
     }
 
-    if point.1 > 0 && height_map[point.0][point.1 - 1] >= current_val {
-        size += determine_basin(Point(point.0, point.1 - 1), &height_map, visited);
+    fn neighbours(&self, point: Point) -> impl Iterator<Item = Point> {
+        let Point(i, j) = point;
+        let (rows, cols) = (self.rows(), self.cols());
+        [
+            (i > 0).then(|| Point(i - 1, j)),
+            (j > 0).then(|| Point(i, j - 1)),
+            (i + 1 < rows).then(|| Point(i + 1, j)),
+            (j + 1 < cols).then(|| Point(i, j + 1)),
+        ]
+        .into_iter()
+        .flatten()
     }
 
-    if point.0 < row_len - 1 && height_map[point.0 + 1][point.1] >= current_val {
-        size += determine_basin(Point(point.0 + 1, point.1), &height_map, visited);
+    fn is_lowest(&self, point: Point) -> bool {
+        !self.is_wall(point)
+            && self
+                .neighbours(point)
+                .all(|next| self.height(next) > self.height(point))
     }
 
-    if point.1 < col_len - 1 && height_map[point.0][point.1 + 1] >= current_val {
-        size += determine_basin(Point(point.0, point.1 + 1), &height_map, visited);
+    fn index(&self, point: Point) -> usize {
+        point.0 * self.cols() + point.1
     }
+}
 
-    size + 1
+/**
+ * Everything known about one basin. depth_profile[h] is the number of cells at height h,
+ * and the bounding box runs from top_left to bottom_right inclusive.
+ * The low point is the first of the lowest cells, reading row by row.
+ */
+#[derive(Debug)]
+struct Basin {
+    low_point: Point,
+    size: u32,
+    depth_profile: Vec<u32>,
+    top_left: Point,
+    bottom_right: Point,
 }
 
-fn find_basins(lowest_points: &Vec<Point>, height_map: &Vec<Vec<u32>>) -> Vec<u32> {
-    let mut basins = vec![];
+impl Basin {
+    fn new(first: Point, num_heights: usize) -> Basin {
+        Basin {
+            low_point: first,
+            size: 0,
+            depth_profile: vec![0; num_heights],
+            top_left: first,
+            bottom_right: first,
+        }
+    }
+
+    fn low_height(&self) -> Option<usize> {
+        self.depth_profile.iter().position(|count| *count > 0)
+    }
 
-    let mut visited = HashSet::new();
-    for p in lowest_points {
-        basins.push(determine_basin(*p, &height_map, &mut visited));
+    fn add(&mut self, point: Point, height: u32) {
+        if self.low_height().is_none_or(|low| (height as usize) < low) {
+            self.low_point = point;
+        }
+
+        self.size += 1;
+        self.depth_profile[height as usize] += 1;
+        self.top_left = Point(self.top_left.0.min(point.0), self.top_left.1.min(point.1));
+        self.bottom_right = Point(
+            self.bottom_right.0.max(point.0),
+            self.bottom_right.1.max(point.1),
+        );
     }
 
-    basins
+    // Height difference between the low point and the highest cell in the basin
+    fn depth(&self) -> usize {
+        let highest = self.depth_profile.iter().rposition(|count| *count > 0);
+        match (self.low_height(), highest) {
+            (Some(low), Some(high)) => high - low,
+            _ => 0,
+        }
+    }
 }
 
-fn is_lowest(i: usize, j: usize, height_map: &Vec<Vec<u32>>) -> bool {
-    let row_len = height_map.len();
-    let col_len = height_map[0].len();
+/**
+ * Basin id of every cell, None for the walls
+ */
+struct BasinLabels {
+    labels: Vec<Vec<Option<usize>>>,
+    basins: Vec<Basin>,
+}
 
-    let current_val = height_map[i][j];
+/**
+ * Union-find over the cells, flattened row by row.
+ * Finds halve the path as they go, and unions attach the smaller set under the larger.
+ */
+struct DisjointSet {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
 
-    if i > 0 && height_map[i - 1][j] <= current_val {
-        return false;
+impl DisjointSet {
+    fn new(count: usize) -> DisjointSet {
+        DisjointSet {
+            parent: (0..count).collect(),
+            size: vec![1; count],
+        }
     }
 
-    if j > 0 && height_map[i][j - 1] <= current_val {
-        return false;
+    fn find(&mut self, mut node: usize) -> usize {
+        while self.parent[node] != node {
+            self.parent[node] = self.parent[self.parent[node]];
+            node = self.parent[node];
+        }
+
+        node
     }
 
-    if i < row_len - 1 && height_map[i + 1][j] <= current_val {
-        return false;
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+/*
+    Every open cell drains into its lowest neighbour, as long as that neighbour is strictly lower.
+    Joining each cell to the one it drains into groups the cells by the low point they end up at.
+
+    Cells with no lower neighbour are the bottom of a basin. Usually that's a single low point,
+    but when the bottom is a flat plateau, the cells on it are joined to their equal neighbours
+    so the whole plateau is one basin. Shelves partway up drain normally, since their cells
+    have somewhere lower to go.
+
+    Nothing here relies on walls separating the basins, so it also works when two basins
+    meet along a ridge below the impassable height.
+*/
+fn find_basins(height_map: &HeightMap) -> BasinLabels {
+    let mut sets = DisjointSet::new(height_map.rows() * height_map.cols());
+
+    for point in height_map.points() {
+        if height_map.is_wall(point) {
+            continue;
+        }
+
+        let height = height_map.height(point);
+        let lowest = height_map
+            .neighbours(point)
+            .min_by_key(|next| height_map.height(*next));
+
+        match lowest {
+            Some(next) if height_map.height(next) < height => {
+                sets.union(height_map.index(point), height_map.index(next));
+            }
+            _ => {
+                for next in height_map.neighbours(point) {
+                    if height_map.height(next) == height {
+                        sets.union(height_map.index(point), height_map.index(next));
+                    }
+                }
+            }
+        }
     }
 
-    if j < col_len - 1 && height_map[i][j + 1] <= current_val {
-        return false;
+    // Basins are numbered in the order their first cell turns up
+    let num_heights = height_map.impassable as usize;
+    let mut labels = vec![vec![None; height_map.cols()]; height_map.rows()];
+    let mut basin_ids = vec![None; height_map.rows() * height_map.cols()];
+    let mut basins: Vec<Basin> = vec![];
+    for point in height_map.points() {
+        if height_map.is_wall(point) {
+            continue;
+        }
+
+        let root = sets.find(height_map.index(point));
+        let id = *basin_ids[root].get_or_insert_with(|| {
+            basins.push(Basin::new(point, num_heights));
+            basins.len() - 1
+        });
+
+        labels[point.0][point.1] = Some(id);
+        basins[id].add(point, height_map.height(point));
     }
 
-    true
+    BasinLabels { labels, basins }
 }
 
-fn run_problem(variant: QVariant) {
-    let file = File::open("inputs/day9/input.txt").unwrap();
-    let reader = BufReader::new(file);
+#[derive(Debug, Copy, Clone)]
+enum Aggregate {
+    Product,
+    Sum,
+}
 
-    let mut height_map: Vec<Vec<u32>> = vec![];
-    for line in reader.lines() {
-        let input = line.unwrap();
-        let mut row = vec![];
-        for i in input.chars().map(|c| c.to_digit(10).unwrap()) {
-            row.push(i);
+/**
+ * Combines the sizes of the K largest basins.
+ * -o top=K (default 3) and -o aggregate=product|sum (default product)
+ */
+fn top_basins(basins: &[Basin]) -> u64 {
+    let top: usize = options::get_or("top", 3);
+    let aggregate = match options::get_or("aggregate", "product".to_string()).as_str() {
+        "product" => Aggregate::Product,
+        "sum" => Aggregate::Sum,
+        other => panic!("Unknown aggregate '{}'", other),
+    };
+
+    if top > basins.len() {
+        panic!(
+            "Asked for the top {} basins but there are only {}",
+            top,
+            basins.len()
+        );
+    }
+
+    let mut sizes: Vec<u64> = basins.iter().map(|basin| basin.size as u64).collect();
+    sizes.sort_by(|a, b| b.cmp(a));
+
+    match aggregate {
+        Aggregate::Product => sizes[..top].iter().product(),
+        Aggregate::Sum => sizes[..top].iter().sum(),
+    }
+}
+
+const WALL_COLOUR: [u8; 3] = [24, 24, 24];
+const LOW_POINT_COLOUR: [u8; 3] = [255, 255, 255];
+
+/**
+ * Evenly spread hues, stepping round the colour wheel by the golden ratio so basins
+ * next to each other in the numbering don't end up with similar colours
+ */
+fn basin_colour(id: usize) -> [f64; 3] {
+    let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    match hue as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
+
+/**
+ * Colour of every cell: walls dark, the lowest cells of each basin white,
+ * and everything else in its basin's colour, brighter the higher it is
+ */
+fn cell_colours(height_map: &HeightMap, labelled: &BasinLabels) -> Vec<Vec<[u8; 3]>> {
+    let basin_colours: Vec<[f64; 3]> = (0..labelled.basins.len()).map(basin_colour).collect();
+
+    (0..height_map.rows())
+        .map(|i| {
+            (0..height_map.cols())
+                .map(|j| {
+                    let Some(id) = labelled.labels[i][j] else {
+                        return WALL_COLOUR;
+                    };
+
+                    let basin = &labelled.basins[id];
+                    let height = height_map.height(Point(i, j)) as usize;
+                    if basin.low_height() == Some(height) {
+                        return LOW_POINT_COLOUR;
+                    }
+
+                    let shade = 0.35 + 0.65 * (height + 1) as f64 / height_map.impassable as f64;
+                    basin_colours[id].map(|channel| (channel * shade * 255.0) as u8)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn write_ppm(colours: &[Vec<[u8; 3]>], path: &str) {
+    let height = colours.len();
+    let width = colours[0].len();
+
+    let mut data = vec![];
+    data.extend(format!("P6\n{} {}\n255\n", width, height).bytes());
+    for colour in colours.iter().flatten() {
+        data.extend(colour);
+    }
+
+    let mut file = File::create(path).unwrap();
+    file.write_all(&data).unwrap();
+    println!("Wrote {}x{} image to {}", width, height, path);
+}
+
+fn is_dark(colour: [u8; 3]) -> bool {
+    colour.iter().map(|channel| *channel as u32).sum::<u32>() < 192
+}
+
+// Every height drawn on its cell's colour, using 24 bit ANSI escapes
+fn print_ansi(height_map: &HeightMap, colours: &[Vec<[u8; 3]>]) {
+    let mut text = String::new();
+    for (i, row) in colours.iter().enumerate() {
+        for (j, [r, g, b]) in row.iter().enumerate() {
+            let foreground = if is_dark([*r, *g, *b]) {
+                "38;2;160;160;160"
+            } else {
+                "38;2;0;0;0"
+            };
+            text.push_str(&format!(
+                "\x1b[{};48;2;{};{};{}m{}",
+                foreground,
+                r,
+                g,
+                b,
+                height_map.height(Point(i, j))
+            ));
         }
+        text.push_str("\x1b[0m\n");
+    }
+
+    print!("{}", text);
+}
+
+/**
+ * -o render=ansi prints the map in colour, any other value is the path of a PPM image to write
+ */
+fn render(height_map: &HeightMap, labelled: &BasinLabels, target: &str) {
+    let colours = cell_colours(height_map, labelled);
+    match target {
+        "ansi" => print_ansi(height_map, &colours),
+        path => write_ppm(&colours, path),
+    }
+}
+
+fn print_basins(labelled: &BasinLabels) {
+    for (id, basin) in labelled.basins.iter().enumerate() {
+        let profile: Vec<String> = basin
+            .depth_profile
+            .iter()
+            .map(|count| count.to_string())
+            .collect();
+        println!(
+            "Basin {} - low point ({}, {}), size {}, depth {}, box ({}, {}) to ({}, {}), cells by height [{}]",
+            id,
+            basin.low_point.0,
+            basin.low_point.1,
+            basin.size,
+            basin.depth(),
+            basin.top_left.0,
+            basin.top_left.1,
+            basin.bottom_right.0,
+            basin.bottom_right.1,
+            profile.join(", ")
+        );
+    }
 
-        height_map.push(row);
+    let largest = labelled.basins.iter().max_by_key(|basin| basin.size);
+    let deepest = labelled.basins.iter().max_by_key(|basin| basin.depth());
+    if let (Some(largest), Some(deepest)) = (largest, deepest) {
+        println!(
+            "Largest - {} cells at ({}, {})",
+            largest.size, largest.low_point.0, largest.low_point.1
+        );
+        println!(
+            "Deepest - {} levels at ({}, {})",
+            deepest.depth(),
+            deepest.low_point.0,
+            deepest.low_point.1
+        );
     }
 
-    let row_len = height_map.len();
-    let col_len = height_map[0].len();
+    let walls = labelled
+        .labels
+        .iter()
+        .flatten()
+        .filter(|label| label.is_none())
+        .count();
+    println!("{} basins, {} wall cells", labelled.basins.len(), walls);
+}
 
-    let mut lowest_points: Vec<Point> = vec![];
+/*
+    Trapping rain water in 2D. Water on a cell can only rise as high as the lowest wall
+    between it and the edge of the map, where it would spill off.
+
+    The border cells can't hold anything, so they start in a min-heap. Popping the lowest
+    cell on the boundary, each unvisited neighbour gets a water level of at least that cell's
+    level, since the popped cell is the lowest way out of the enclosed area. Neighbours then
+    join the boundary themselves. Every cell is visited once, so this is O(n log n).
+
+    Walls play no special part here, they're only tall terrain.
+*/
+fn flood(height_map: &HeightMap) -> Vec<Vec<u32>> {
+    let mut levels = height_map.heights.clone();
+    let mut visited = vec![vec![false; height_map.cols()]; height_map.rows()];
+    let mut boundary = BinaryHeap::new();
+
+    for point in height_map.points() {
+        let Point(i, j) = point;
+        if i == 0 || j == 0 || i == height_map.rows() - 1 || j == height_map.cols() - 1 {
+            visited[i][j] = true;
+            boundary.push(Reverse((height_map.height(point), i, j)));
+        }
+    }
 
-    let mut risk = 0;
-    for i in 0..row_len {
-        for j in 0..col_len {
-            if is_lowest(i, j, &height_map) {
-                risk += height_map[i][j] + 1;
-                lowest_points.push(Point(i, j));
+    while let Some(Reverse((level, i, j))) = boundary.pop() {
+        for next in height_map.neighbours(Point(i, j)) {
+            if visited[next.0][next.1] {
+                continue;
             }
+
+            visited[next.0][next.1] = true;
+            let next_level = level.max(height_map.height(next));
+            levels[next.0][next.1] = next_level;
+            boundary.push(Reverse((next_level, next.0, next.1)));
+        }
+    }
+
+    levels
+}
+
+fn print_flood(height_map: &HeightMap, levels: &[Vec<u32>]) {
+    let depths: Vec<u32> = height_map
+        .points()
+        .map(|point| levels[point.0][point.1] - height_map.height(point))
+        .collect();
+
+    let volume: u32 = depths.iter().sum();
+    let wet = depths.iter().filter(|depth| **depth > 0).count();
+    let deepest = depths.iter().max().unwrap_or(&0);
+    println!(
+        "{} cells under water, deepest {}, total volume {}",
+        wet, deepest, volume
+    );
+
+    // -o levels=path writes the water surface of every cell, in the same format as the input
+    if let Some(path) = options::get::<String>("levels") {
+        let mut text = String::new();
+        for row in levels {
+            text.extend(
+                row.iter()
+                    .map(|level| char::from_digit(*level, 10).unwrap()),
+            );
+            text.push('\n');
         }
+
+        let mut file = File::create(&path).unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        println!("Wrote water levels to {}", path);
     }
 
-    let mut basins = find_basins(&lowest_points, &height_map);
-    basins.sort_by(|a, b| b.cmp(a));
+    println!("Answer - {}", volume);
+}
+
+fn run_problem(variant: QVariant) {
+    let file = File::open("inputs/day9/input.txt").unwrap();
+    let reader = BufReader::new(file);
+
+    let height_map = HeightMap::parse(reader);
+
+    let render_target = options::get::<String>("render");
+    let labelled = match (&variant, &render_target) {
+        (QVariant::Part1 | QVariant::Flood, None) => None,
+        _ => Some(find_basins(&height_map)),
+    };
 
-    let mut top_basin_size = 1;
-    for basin in &basins[0..3] {
-        top_basin_size *= basin;
+    if let (Some(target), Some(labelled)) = (&render_target, &labelled) {
+        render(&height_map, labelled, target);
     }
 
     match variant {
         QVariant::Part1 => {
+            let risk: u32 = height_map
+                .points()
+                .filter(|point| height_map.is_lowest(*point))
+                .map(|point| height_map.height(point) + 1)
+                .sum();
             println!("Answer - {}", risk);
         }
         QVariant::Part2 => {
-            println!("Answer - {}", top_basin_size);
+            println!("Answer - {}", top_basins(&labelled.unwrap().basins));
+        }
+        QVariant::Basins => {
+            print_basins(&labelled.unwrap());
+        }
+        QVariant::Flood => {
+            print_flood(&height_map, &flood(&height_map));
         }
     }
 }
@@ -148,3 +564,11 @@ pub fn part1() {
 pub fn part2() {
     run_problem(QVariant::Part2);
 }
+
+pub fn part3() {
+    run_problem(QVariant::Basins);
+}
+
+pub fn part4() {
+    run_problem(QVariant::Flood);
+}
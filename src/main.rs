@@ -1,6 +1,7 @@
 use std::time::Instant;
 
 mod days;
+mod options;
 use days::*;
 
 extern crate clap;
@@ -46,10 +47,24 @@ fn main() {
                 .default_value("1")
                 .help("chooses the part"),
         )
+        .arg(
+            Arg::with_name("option")
+                .short("o")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .help("sets a key=value option for the chosen day"),
+        )
         .get_matches();
 
     let day = matches.value_of("day").unwrap();
     let part = matches.value_of("part").unwrap();
+    options::init(
+        matches
+            .values_of("option")
+            .map(|values| values.collect())
+            .unwrap_or_default(),
+    );
 
     print!("Day {} - ", day);
     match day {
@@ -60,13 +75,22 @@ fn main() {
             run_part(vec![day2::part1, day2::part2], part);
         }
         "3" => {
-            run_part(vec![day3::part1, day3::part2], part);
+            run_part(vec![day3::part1, day3::part2, day3::part3], part);
         }
         "4" => {
-            run_part(vec![day4::part1, day4::part2], part);
+            run_part(
+                vec![
+                    day4::part1,
+                    day4::part2,
+                    day4::part3,
+                    day4::part4,
+                    day4::part5,
+                ],
+                part,
+            );
         }
         "5" => {
-            run_part(vec![day5::part1, day5::part2], part);
+            run_part(vec![day5::part1, day5::part2, day5::part3], part);
         }
         "6" => {
             run_part(vec![day6::part1, day6::part2], part);
@@ -75,13 +99,19 @@ fn main() {
             run_part(vec![day7::part1, day7::part2], part);
         }
         "8" => {
-            run_part(vec![day8::part1, day8::part2], part);
+            run_part(
+                vec![day8::part1, day8::part2, day8::part3, day8::part4],
+                part,
+            );
         }
         "9" => {
-            run_part(vec![day9::part1, day9::part2], part);
+            run_part(
+                vec![day9::part1, day9::part2, day9::part3, day9::part4],
+                part,
+            );
         }
         "10" => {
-            run_part(vec![day10::part1, day10::part2], part);
+            run_part(vec![day10::part1, day10::part2, day10::part3], part);
         }
         "11" => {
             run_part(vec![day11::part1, day11::part2], part);
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/*
+    Extra key=value settings passed on the command line with -o, e.g. `-d 4 -p 1 -o diagonals=true`.
+    Days read them through `get` / `flag` so the part functions can stay as plain fn().
+*/
+static OPTIONS: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+pub fn init(values: Vec<&str>) {
+    let mut map = HashMap::new();
+    for value in values {
+        let (key, val) = match value.split_once('=') {
+            Some(pair) => pair,
+            // A bare key is treated as an enabled flag
+            None => (value, "true"),
+        };
+
+        map.insert(key.trim().to_string(), val.trim().to_string());
+    }
+
+    OPTIONS.set(map).expect("Options can only be set once");
+}
+
+pub fn get<T: FromStr>(key: &str) -> Option<T> {
+    let value = OPTIONS.get()?.get(key)?;
+    match value.parse::<T>() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => panic!("Invalid value '{}' for option '{}'", value, key),
+    }
+}
+
+pub fn get_or<T: FromStr>(key: &str, default: T) -> T {
+    get(key).unwrap_or(default)
+}
+
+pub fn flag(key: &str) -> bool {
+    get_or(key, false)
+}